@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_std::sync::Mutex;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use surf::http::headers::HeaderValue;
+use surf::StatusCode;
+
+use crate::forge::Error;
+
+// GitHub won't accept an App JWT that expires more than ten minutes
+// in the future, so stay a bit under that.
+const JWT_LIFETIME: Duration = Duration::from_secs(9 * 60);
+
+// Backdate the JWT a little, in case GitHub's clock is behind ours.
+const JWT_CLOCK_DRIFT: Duration = Duration::from_secs(60);
+
+// Installation tokens expire after an hour.  Replace them a while
+// before that, so a request doesn't go out with a token that expires
+// while it's in flight.
+const INSTALLATION_TOKEN_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    iat: u64,
+    exp: u64,
+    iss: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessTokenResponse {
+    token: String,
+    expires_at: String,
+}
+
+struct InstallationToken {
+    token: String,
+    expires_at: SystemTime,
+}
+
+pub struct GitHubApp {
+    api_url: String,
+    id: String,
+    key: EncodingKey,
+    installation_id: String,
+    installation_token: Mutex<Option<InstallationToken>>,
+}
+
+impl GitHubApp {
+    pub fn new(api_url: &str, id: String, key: EncodingKey, installation_id: String) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            id,
+            key,
+            installation_id,
+            installation_token: Mutex::new(None),
+        }
+    }
+
+    fn jwt(&self) -> Result<String, Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let claims = Claims {
+            iat: (now - JWT_CLOCK_DRIFT).as_secs(),
+            exp: (now + JWT_LIFETIME).as_secs(),
+            iss: self.id.clone(),
+        };
+
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key).map_err(Error::Jwt)
+    }
+
+    async fn installation_token(&self, user_agent: &OsStr) -> Result<String, Error> {
+        // Holding the lock for the whole exchange means that if
+        // several requests find the token needs refreshing at once,
+        // only the first of them will actually refresh it.
+        let mut installation_token = self.installation_token.lock().await;
+
+        if let Some(InstallationToken { token, expires_at }) = &*installation_token {
+            if *expires_at > SystemTime::now() + INSTALLATION_TOKEN_MARGIN {
+                return Ok(token.clone());
+            }
+        }
+
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            self.api_url, self.installation_id
+        );
+
+        let mut response = surf::post(url)
+            .header("Accept", "application/vnd.github.v3+json")
+            .header(
+                "User-Agent",
+                HeaderValue::from_bytes(user_agent.as_bytes().to_vec()).map_err(Error::Request)?,
+            )
+            .header("Authorization", format!("Bearer {}", self.jwt()?))
+            .send()
+            .await
+            .map_err(Error::Request)?;
+
+        let status = response.status();
+        if status != StatusCode::Created {
+            return Err(Error::Response(status));
+        }
+
+        let AccessTokenResponse { token, expires_at } =
+            response.body_json().await.map_err(Error::Deserialization)?;
+
+        *installation_token = Some(InstallationToken {
+            token: token.clone(),
+            expires_at: humantime::parse_rfc3339(&expires_at).map_err(Error::Timestamp)?,
+        });
+
+        Ok(token)
+    }
+}
+
+pub enum TokenProvider {
+    /// A token that is used as is for the lifetime of the process,
+    /// e.g. a personal access token.
+    Static(OsString),
+
+    /// Short-lived installation tokens, obtained and refreshed on
+    /// demand by authenticating as a GitHub App.
+    App(GitHubApp),
+}
+
+impl TokenProvider {
+    pub async fn token(&self, user_agent: &OsStr) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Static(token) => Ok(token.as_bytes().to_vec()),
+            Self::App(app) => Ok(app.installation_token(user_agent).await?.into_bytes()),
+        }
+    }
+}
@@ -3,10 +3,13 @@
 
 use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
 
 use once_cell::sync::Lazy;
 use regex::{Regex, RegexSet};
 
+use crate::releases::Releases;
+
 const NEXT_BRANCH_TABLE: [(&str, &str); 10] = [
     (r"\Astaging\z", "staging-next"),
     (r"\Astaging-next\z", "master"),
@@ -20,6 +23,12 @@ const NEXT_BRANCH_TABLE: [(&str, &str); 10] = [
     (r"\Astaging-((2[1-9]|[3-90].)\.\d{2})\z", "staging-next-$1"),
 ];
 
+/// The most branches a change can go on to reach.  Real graphs are
+/// much smaller than this, so a graph that gets this big must be the
+/// result of a mistake in the table, like a rule that keeps making up
+/// new branch names.
+const MAX_BRANCHES: usize = 64;
+
 static BRANCH_NEXTS: Lazy<BTreeMap<&str, Vec<&str>>> = Lazy::new(|| {
     NEXT_BRANCH_TABLE
         .iter()
@@ -42,6 +51,20 @@ static BRANCH_PATTERNS: Lazy<Vec<Regex>> = Lazy::new(|| {
 
 static BRANCH_REGEXES: Lazy<RegexSet> = Lazy::new(|| RegexSet::new(BRANCH_NEXTS.keys()).unwrap());
 
+/// The names of the branches that changes are merged into, written so
+/// that it can be used as a POSIX extended regular expression too.
+pub const DEVELOPMENT_BRANCH_PATTERN: &str = "(master|release-[0-9.]+|staging(-next)?(-[0-9.]+)?)";
+
+static DEVELOPMENT_BRANCH: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"\A{}\z", DEVELOPMENT_BRANCH_PATTERN)).unwrap());
+
+/// Whether `branch` is one that changes are merged into, as opposed
+/// to a channel branch, which only ever gets fast-forwarded to
+/// commits from another branch.
+pub fn is_development_branch(branch: &str) -> bool {
+    DEVELOPMENT_BRANCH.is_match(branch)
+}
+
 pub fn next_branches(branch: &str) -> Vec<Cow<str>> {
     BRANCH_REGEXES
         .matches(branch)
@@ -57,6 +80,188 @@ pub fn next_branches(branch: &str) -> Vec<Cow<str>> {
         .collect()
 }
 
+#[derive(Debug)]
+pub enum Error {
+    Cycle(Vec<String>),
+    TooManyBranches(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use Error::*;
+        match self {
+            Cycle(branches) => write!(f, "Branch cycle: {}", branches.join(" -> ")),
+            TooManyBranches(root) => {
+                write!(f, "More than {} branches come after {}", MAX_BRANCHES, root)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The branches that changes to a branch go on to reach.  A branch
+/// that comes after more than one other branch is only in the graph
+/// once, and the graph is checked not to have any cycles, so it can
+/// be walked without worrying about never reaching the end.
+#[derive(Debug)]
+pub struct Graph {
+    root: String,
+    nexts: BTreeMap<String, Vec<String>>,
+}
+
+impl Graph {
+    pub fn new(root: &str) -> Result<Self, Error> {
+        Self::with_nexts(root, &|branch| {
+            next_branches(branch)
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect()
+        })
+    }
+
+    /// The graph after `root`, leaving out the branches of releases
+    /// that reached their end of life long ago.
+    pub fn supported(root: &str, releases: &Releases) -> Result<Self, Error> {
+        Self::with_nexts(root, &|branch| {
+            next_branches(branch)
+                .into_iter()
+                .filter(|next| !releases.is_long_gone(next))
+                .map(Cow::into_owned)
+                .collect()
+        })
+    }
+
+    /// The graph after `root`, according to `next_branches` instead
+    /// of the table.
+    pub fn with_nexts(
+        root: &str,
+        next_branches: &dyn Fn(&str) -> Vec<String>,
+    ) -> Result<Self, Error> {
+        let mut graph = Self {
+            root: root.to_string(),
+            nexts: BTreeMap::new(),
+        };
+        graph.visit(root, next_branches, &mut Vec::new())?;
+        Ok(graph)
+    }
+
+    /// Add `branch`, and everything after it, to the graph.  `path`
+    /// is the branches that led to `branch`.
+    fn visit(
+        &mut self,
+        branch: &str,
+        next_branches: &dyn Fn(&str) -> Vec<String>,
+        path: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        if let Some(start) = path.iter().position(|b| b == branch) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(branch.to_string());
+            return Err(Error::Cycle(cycle));
+        }
+
+        if self.nexts.contains_key(branch) {
+            return Ok(());
+        }
+
+        if self.nexts.len() == MAX_BRANCHES {
+            return Err(Error::TooManyBranches(self.root.clone()));
+        }
+
+        let nexts = next_branches(branch);
+        self.nexts.insert(branch.to_string(), nexts.clone());
+
+        path.push(branch.to_string());
+        for next in nexts.iter() {
+            self.visit(next, next_branches, path)?;
+        }
+        path.pop();
+
+        Ok(())
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// The branches that come straight after `branch`.
+    pub fn nexts(&self, branch: &str) -> &[String] {
+        self.nexts.get(branch).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn contains(&self, branch: &str) -> bool {
+        self.nexts.contains_key(branch)
+    }
+}
+
+/// Whether changes to `from` eventually make their way to `to`.
+pub fn is_downstream(from: &str, to: &str) -> bool {
+    match Graph::new(from) {
+        Ok(graph) => from != to && graph.contains(to),
+        Err(_) => false,
+    }
+}
+
+/// The branches that the table names outright, rather than with a
+/// pattern, like "staging" and "master".
+fn named_branches() -> impl Iterator<Item = &'static str> {
+    NEXT_BRANCH_TABLE
+        .iter()
+        .flat_map(|(pattern, next)| {
+            let pattern = pattern
+                .strip_prefix(r"\A")
+                .and_then(|p| p.strip_suffix(r"\z"));
+            pattern.into_iter().chain(Some(*next))
+        })
+        .filter(|branch| branch.chars().all(|c| c.is_ascii_lowercase() || c == '-'))
+}
+
+/// Values to fill in the patterns in the table with, so that the
+/// rules for them can be checked too.
+const SAMPLE_VERSIONS: [&str; 6] = ["unstable", "00.11", "18.03", "20.09", "21.05", "30.05"];
+
+/// A branch matching `pattern`, with its capture group replaced by
+/// `value`, if that gives a branch the pattern matches.
+fn instantiate(pattern: &str, value: &str) -> Option<String> {
+    let inner = pattern.strip_prefix(r"\A")?.strip_suffix(r"\z")?;
+    let start = inner.find('(')?;
+
+    let mut depth = 0;
+    let end = inner[start..].find(|c: char| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    })?;
+
+    let branch = format!("{}{}{}", &inner[..start], value, &inner[start + end + 1..]);
+    if Regex::new(pattern).ok()?.is_match(&branch) {
+        Some(branch)
+    } else {
+        None
+    }
+}
+
+/// Check that the graph after each branch the table names outright,
+/// and after the branches its patterns match for each of
+/// `SAMPLE_VERSIONS`, has no cycles, so that a mistake in the table
+/// is found when pr-tracker starts, rather than when someone tracks
+/// a PR.
+pub fn validate() -> Result<(), Error> {
+    let samples = NEXT_BRANCH_TABLE.iter().flat_map(|(pattern, _)| {
+        SAMPLE_VERSIONS
+            .iter()
+            .filter_map(move |value| instantiate(pattern, value))
+    });
+
+    for branch in named_branches().map(str::to_string).chain(samples) {
+        Graph::new(&branch)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,4 +307,79 @@ mod tests {
 	let res = next_branches("release-20.09");
 	assert_eq!(res, vec!["nixpkgs-20.09-darwin", "nixos-20.09-small"]);
     }
+
+    #[test]
+    fn development_branches() {
+	for branch in &["master", "staging", "staging-next", "release-21.05", "staging-21.05", "staging-next-21.05"] {
+	    assert!(is_development_branch(branch), "{}", branch);
+	}
+    }
+
+    #[test]
+    fn channel_branches() {
+	for branch in &["nixos-unstable", "nixos-21.05-small", "nixpkgs-unstable", "nixpkgs-21.05-darwin", "HEAD"] {
+	    assert!(!is_development_branch(branch), "{}", branch);
+	}
+    }
+
+    #[test]
+    fn graph() {
+	let graph = Graph::new("staging").unwrap();
+	assert_eq!(graph.root(), "staging");
+	assert_eq!(graph.nexts("master"), ["nixpkgs-unstable", "nixos-unstable-small"]);
+	assert!(graph.contains("nixos-unstable"));
+	assert!(!graph.contains("release-21.05"));
+    }
+
+    #[test]
+    fn shared() {
+	let graph = Graph::with_nexts("a", &|branch| match branch {
+	    "a" => vec!["b".to_string(), "c".to_string()],
+	    "b" | "c" => vec!["d".to_string()],
+	    _ => vec![],
+	})
+	.unwrap();
+	assert_eq!(graph.nexts("c"), ["d"]);
+    }
+
+    #[test]
+    fn cycle() {
+	let result = Graph::with_nexts("a", &|branch| match branch {
+	    "a" => vec!["b".to_string()],
+	    _ => vec!["a".to_string()],
+	});
+	assert_eq!(result.unwrap_err().to_string(), "Branch cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn too_many_branches() {
+	let result = Graph::with_nexts("a", &|branch| vec![format!("{}a", branch)]);
+	assert!(matches!(result, Err(Error::TooManyBranches(_))));
+    }
+
+    #[test]
+    fn valid() {
+	assert!(named_branches().any(|branch| branch == "staging"));
+	assert!(named_branches().all(|branch| !branch.contains('$')));
+	validate().unwrap();
+    }
+
+    #[test]
+    fn samples() {
+	assert_eq!(instantiate(r"\Astaging-next-([\d.]+)\z", "21.05").as_deref(), Some("staging-next-21.05"));
+	assert_eq!(instantiate(r"\Anixos-(.*)-small\z", "unstable").as_deref(), Some("nixos-unstable-small"));
+	assert_eq!(instantiate(r"\Astaging-((1.|20)\.\d{2})\z", "20.09").as_deref(), Some("staging-20.09"));
+	assert_eq!(instantiate(r"\Astaging-((1.|20)\.\d{2})\z", "21.05"), None);
+	assert_eq!(instantiate(r"\Astaging\z", "21.05"), None);
+    }
+
+    #[test]
+    fn downstream() {
+	assert!(is_downstream("staging-next", "master"));
+	assert!(is_downstream("staging", "nixos-unstable"));
+	assert!(is_downstream("staging-21.05", "nixos-21.05"));
+	assert!(!is_downstream("master", "staging-next"));
+	assert!(!is_downstream("master", "master"));
+	assert!(!is_downstream("master", "release-21.05"));
+    }
 }
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A map whose entries are forgotten after a fixed amount of time,
+/// for results that are expensive to work out but fine to be a
+/// little out of date.  At most `capacity` entries are kept, so that
+/// requests for lots of different keys can't use up all the memory.
+pub struct Cache<K, V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Cache<K, V> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Expired entries are otherwise only removed when they're
+        // looked up again, which they might never be.
+        let ttl = self.ttl;
+        entries.retain(|_, (inserted, _)| inserted.elapsed() < ttl);
+
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hit() {
+        let cache = Cache::new(Duration::from_secs(60), 8);
+        cache.insert("foo", 1);
+        assert_eq!(cache.get(&"foo"), Some(1));
+        assert_eq!(cache.get(&"bar"), None);
+    }
+
+    #[test]
+    fn full() {
+        let cache = Cache::new(Duration::from_secs(60), 2);
+        cache.insert("foo", 1);
+        cache.insert("bar", 2);
+        cache.insert("baz", 3);
+        assert_eq!(cache.get(&"foo"), None);
+        assert_eq!(cache.get(&"bar"), Some(2));
+        assert_eq!(cache.get(&"baz"), Some(3));
+    }
+
+    #[test]
+    fn expired() {
+        let cache = Cache::new(Duration::from_secs(0), 8);
+        cache.insert("foo", 1);
+        assert_eq!(cache.get(&"foo"), None);
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Finding out which commit each channel was last released at.
+//!
+//! A channel branch is updated as soon as Hydra has finished building
+//! it, but the channel itself, which is what users actually download,
+//! is only released once its tarballs have been uploaded, so it can
+//! lag behind the branch.  Each channel's released commit is
+//! published at `<base URL>/<channel>/git-revision`, which for NixOS
+//! is https://channels.nixos.org.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use surf::http::headers::HeaderValue;
+use surf::StatusCode;
+
+use crate::cache::Cache;
+use crate::forge::Error;
+
+/// Channels are released a few times a day at most, so there's no
+/// point asking about them on every request.  There are only a few
+/// dozen channels.
+static RELEASES: Lazy<Cache<(String, String), Release>> =
+    Lazy::new(|| Cache::new(Duration::from_secs(5 * 60), 256));
+
+/// Whether `branch` is one that's released as a channel.
+pub fn is_channel(branch: &str) -> bool {
+    branch.starts_with("nixos-") || branch.starts_with("nixpkgs-")
+}
+
+#[derive(Clone, Debug)]
+pub struct Release {
+    pub commit: String,
+
+    /// When the release was published, as given by the server.
+    pub released: Option<String>,
+}
+
+pub struct Channels<'a> {
+    base_url: &'a str,
+    user_agent: &'a OsStr,
+}
+
+impl<'a> Channels<'a> {
+    pub fn new(base_url: &'a str, user_agent: &'a OsStr) -> Self {
+        Self {
+            base_url,
+            user_agent,
+        }
+    }
+
+    /// The latest release of `channel`.
+    pub async fn release(&self, channel: &str) -> Result<Release, Error> {
+        let key = (self.base_url.to_string(), channel.to_string());
+        if let Some(release) = RELEASES.get(&key) {
+            return Ok(release);
+        }
+
+        let url = format!(
+            "{}/{}/git-revision",
+            self.base_url.trim_end_matches('/'),
+            channel
+        );
+        let mut response = surf::get(url)
+            .header(
+                "User-Agent",
+                HeaderValue::from_bytes(self.user_agent.as_bytes().to_vec())
+                    .map_err(Error::Request)?,
+            )
+            .send()
+            .await
+            .map_err(Error::Request)?;
+
+        let status = response.status();
+        if status == StatusCode::NotFound {
+            return Err(Error::NotFound);
+        } else if !status.is_success() {
+            return Err(Error::Response(status));
+        }
+
+        let released = response
+            .header("Last-Modified")
+            .map(|value| value.last().as_str().to_string());
+        let commit = response
+            .body_string()
+            .await
+            .map_err(Error::Deserialization)?
+            .trim()
+            .to_string();
+
+        let release = Release { commit, released };
+        RELEASES.insert(key, release.clone());
+        Ok(release)
+    }
+}
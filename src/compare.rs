@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Comparing two branches, to find the PRs that have landed on one,
+//! but haven't made it to the other yet.
+
+use askama::Template;
+use http_types::mime;
+use serde::Deserialize;
+use tide::{Request, Response};
+
+use crate::branches::is_downstream;
+use crate::forge::Forge;
+use crate::history::{self, Change};
+use crate::nixpkgs::Nixpkgs;
+use crate::CONFIG;
+
+/// The most PRs to list.  Between staging-next and master, there can
+/// be a lot.
+const MAX_PRS: usize = 500;
+
+#[derive(Debug, Default, Template)]
+#[template(path = "compare.html")]
+pub struct CompareTemplate {
+    mount: String,
+    from: Option<String>,
+    to: Option<String>,
+    pub error: Option<String>,
+    pub prs: Vec<Change>,
+    more: bool,
+    source_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+pub async fn compare(
+    forge: &dyn Forge,
+    nixpkgs: &Nixpkgs<'_>,
+    from: &str,
+    to: &str,
+    status: &mut u16,
+    page: &mut CompareTemplate,
+) {
+    if !is_downstream(from, to) {
+        *status = 400;
+        page.error = Some(format!("Changes to {} don't go on to {}.", from, to));
+        return;
+    }
+
+    let branches = match nixpkgs.branches().await {
+        Ok(branches) => branches,
+        Err(e) => {
+            *status = 500;
+            page.error = Some(e.to_string());
+            return;
+        }
+    };
+
+    for branch in &[from, to] {
+        if !branches.iter().any(|b| b == branch) {
+            *status = 404;
+            page.error = Some(format!("No such branch: {}", branch));
+            return;
+        }
+    }
+
+    match history::prs_between(forge, nixpkgs, from, to, MAX_PRS).await {
+        Ok((prs, more)) => {
+            page.prs = prs;
+            page.more = more;
+        }
+
+        Err(e) => {
+            *status = 500;
+            page.error = Some(e.to_string());
+        }
+    }
+}
+
+pub async fn handle_compare<S>(request: Request<S>) -> http_types::Result<Response> {
+    let mut status = 200;
+    let mut page = CompareTemplate {
+        mount: crate::route(""),
+        source_url: CONFIG.source_url.clone(),
+        ..Default::default()
+    };
+
+    let query = request.query::<Query>()?;
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        let forge = crate::forge();
+        let nixpkgs = Nixpkgs::new(&CONFIG.path, &CONFIG.remote);
+        compare(&*forge, &nixpkgs, &from, &to, &mut status, &mut page).await;
+
+        page.from = Some(from);
+        page.to = Some(to);
+    }
+
+    Ok(Response::builder(status)
+        .content_type(mime::HTML)
+        .body(page.render()?)
+        .build())
+}
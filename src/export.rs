@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Writing a PR's tree as a Graphviz or Mermaid graph, for pasting
+//! into documents, or as plain text, for reading in a terminal.
+
+use std::fmt::Write;
+
+use crate::tree::{State, Tree};
+
+struct Node<'a> {
+    id: usize,
+    label: &'a str,
+    state: State,
+
+    /// When things happened to the branch, to be shown under its name.
+    times: Vec<String>,
+}
+
+fn walk<'a>(
+    tree: &'a Tree,
+    parent: Option<usize>,
+    out: &mut Vec<Node<'a>>,
+    edges: &mut Vec<(usize, usize)>,
+) {
+    // A branch that comes after more than one other branch is drawn
+    // once, with an edge from each of them.
+    let shared = if tree.shared {
+        out.iter().find(|node| node.label == tree.branch_name)
+    } else {
+        None
+    };
+
+    let id = match shared.map(|node| node.id) {
+        Some(id) => id,
+        None => {
+            let id = out.len();
+            out.push(Node {
+                id,
+                label: &tree.branch_name,
+                state: tree.state(),
+                times: tree
+                    .released
+                    .iter()
+                    .map(|time| format!("released {}", time))
+                    .collect(),
+            });
+            id
+        }
+    };
+
+    if let Some(parent) = parent {
+        edges.push((parent, id));
+    }
+
+    for child in tree.children.iter() {
+        walk(child, Some(id), out, edges);
+    }
+}
+
+/// The nodes of `tree`, or a single "closed" node if there's no tree
+/// because the PR was closed, and the edges between them.  The PR
+/// was merged into the root of the tree at `merged_at`.
+fn nodes<'a>(
+    merged_at: Option<&str>,
+    tree: Option<&'a Tree>,
+) -> (Vec<Node<'a>>, Vec<(usize, usize)>) {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    match tree {
+        Some(tree) => walk(tree, None, &mut nodes, &mut edges),
+        None => nodes.push(Node {
+            id: 0,
+            label: "closed",
+            state: State::Rejected,
+            times: Vec::new(),
+        }),
+    }
+
+    if let (Some(root), Some(merged_at)) = (nodes.first_mut(), merged_at) {
+        root.times.insert(0, format!("merged {}", merged_at));
+    }
+
+    (nodes, edges)
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn dot(pr_number: &str, merged_at: Option<&str>, tree: Option<&Tree>) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "digraph \"PR #{}\" {{", dot_escape(pr_number)).unwrap();
+    writeln!(out, "  node [shape=box, style=\"rounded,filled\"];").unwrap();
+
+    let (nodes, edges) = nodes(merged_at, tree);
+    for node in nodes.iter() {
+        let mut label = dot_escape(node.label);
+        for time in node.times.iter() {
+            write!(label, "\\n{}", dot_escape(time)).unwrap();
+        }
+
+        writeln!(
+            out,
+            "  n{} [label=\"{}\", class=\"state-{}\", fillcolor=\"{}\"];",
+            node.id,
+            label,
+            node.state,
+            node.state.colour()
+        )
+        .unwrap();
+    }
+
+    for (parent, child) in edges {
+        writeln!(out, "  n{} -> n{};", parent, child).unwrap();
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+pub fn mermaid(merged_at: Option<&str>, tree: Option<&Tree>) -> String {
+    let mut out = String::from("flowchart TD\n");
+
+    let (nodes, edges) = nodes(merged_at, tree);
+    for node in nodes.iter() {
+        let mut label = node.label.replace('"', "#quot;");
+        for time in node.times.iter() {
+            write!(label, "<br>{}", time.replace('"', "#quot;")).unwrap();
+        }
+
+        writeln!(out, "  n{}[\"{}\"]:::{}", node.id, label, node.state).unwrap();
+    }
+
+    for (parent, child) in edges {
+        writeln!(out, "  n{} --> n{}", parent, child).unwrap();
+    }
+
+    for state in State::ALL.iter() {
+        writeln!(out, "  classDef {} fill:{}", state, state.colour()).unwrap();
+    }
+
+    out
+}
+
+/// The ANSI escape sequence to colour text for a state.
+fn ansi_colour(state: State) -> &'static str {
+    match state {
+        State::Accepted => "\x1b[32m",
+        State::Pending => "\x1b[2m",
+        State::Unknown => "\x1b[33m",
+        State::Rejected => "\x1b[31m",
+        State::Reverted => "\x1b[35m",
+        State::Partial => "\x1b[36m",
+        State::Unreleased => "\x1b[34m",
+        State::EndOfLife => "\x1b[90m",
+    }
+}
+
+fn text_line(out: &mut String, prefix: &str, label: &str, state: State, colour: bool) {
+    let label = if colour {
+        format!("{}{}\x1b[0m", ansi_colour(state), label)
+    } else {
+        label.to_string()
+    };
+
+    writeln!(out, "{}{} {}", prefix, state.emoji(), label).unwrap();
+}
+
+fn text_node(out: &mut String, tree: &Tree, first: &str, rest: &str, colour: bool) {
+    let mut label = tree.branch_name.clone();
+    if let Some(backport) = &tree.backport {
+        write!(label, " ({})", backport.description()).unwrap();
+    }
+    if tree.shared {
+        label.push_str(" (continues as above)");
+    }
+    if tree.partial {
+        label.push_str(" (partially present)");
+    }
+    if tree.end_of_life {
+        label.push_str(" (end of life)");
+    }
+    if tree.unreleased {
+        label.push_str(" (not yet in a released channel)");
+    }
+    if let Some(carrier) = &tree.carried_by {
+        write!(label, " (via {}", carrier.short_commit()).unwrap();
+        if let Some(number) = carrier.pr_number {
+            write!(label, " in #{}", number).unwrap();
+        }
+        label.push(')');
+    }
+    if let Some(time) = &tree.rewritten {
+        write!(label, " (branch was rewritten at {})", time).unwrap();
+    }
+    text_line(out, first, &label, tree.state(), colour);
+
+    for (i, child) in tree.children.iter().enumerate() {
+        let (first, next) = match i == tree.children.len() - 1 {
+            true => ("└─ ", "   "),
+            false => ("├─ ", "│  "),
+        };
+
+        let child_first = format!("{}{}", rest, first);
+        let child_rest = format!("{}{}", rest, next);
+        text_node(out, child, &child_first, &child_rest, colour);
+    }
+}
+
+/// An indented tree, drawn with box-drawing characters, optionally
+/// coloured with ANSI escape sequences.
+pub fn text(
+    pr_number: &str,
+    title: Option<&str>,
+    error: Option<&str>,
+    tree: Option<&Tree>,
+    colour: bool,
+) -> String {
+    let mut out = format!("PR #{}", pr_number);
+    if let Some(title) = title {
+        write!(out, ": {}", title).unwrap();
+    }
+    out.push_str("\n\n");
+
+    match tree {
+        Some(tree) => text_node(&mut out, tree, "", "", colour),
+        None => text_line(&mut out, "", "closed", State::Rejected, colour),
+    }
+
+    if let Some(error) = error {
+        writeln!(out, "\n{}", error).unwrap();
+    }
+
+    out
+}
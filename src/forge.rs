@@ -0,0 +1,576 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+// SPDX-FileCopyrightText: 2021 Sumner Evans <me@sumnerevans.com>
+
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::os::unix::ffi::OsStrExt;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use surf::http::headers::HeaderValue;
+use surf::StatusCode;
+
+use crate::auth::TokenProvider;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    Unsupported,
+    Serialization(serde_json::Error),
+    Request(surf::Error),
+    Response(StatusCode),
+    Deserialization(http_types::Error),
+    Jwt(jsonwebtoken::errors::Error),
+    Timestamp(humantime::TimestampError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use Error::*;
+        match self {
+            NotFound => write!(f, "Not found"),
+            Unsupported => write!(f, "Not supported by this forge"),
+            Serialization(e) => write!(f, "Serialization error: {}", e),
+            Request(e) => write!(f, "Request error: {}", e),
+            Response(s) => write!(f, "Unexpected response status: {}", s),
+            Deserialization(e) => write!(f, "Deserialization error: {}", e),
+            Jwt(e) => write!(f, "JWT error: {}", e),
+            Timestamp(e) => write!(f, "Timestamp error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug)]
+pub enum PullRequestStatus {
+    Open,
+    Closed,
+    Merged {
+        /// This field is optional because GitHub doesn't provide us with this information
+        /// for PRs merged before around March 2016.
+        merge_commit_oid: Option<String>,
+    },
+}
+
+/// How far an open pull request has got with being reviewed and
+/// tested.
+#[derive(Debug, Default, Serialize)]
+pub struct ReviewStatus {
+    pub draft: bool,
+
+    /// The forge's verdict on the reviews, e.g. "approved" or
+    /// "changes requested", if reviews are required.
+    pub decision: Option<String>,
+
+    pub approvals: usize,
+    pub conflicting: bool,
+
+    /// The combined state of the checks, e.g. "passing" or "failing",
+    /// if there are any.
+    pub checks: Option<String>,
+
+    pub failing_checks: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct PrInfo {
+    pub number: i64,
+    pub branch: String,
+    pub title: String,
+    pub url: String,
+    pub author: String,
+    pub author_url: Option<String>,
+    pub status: PullRequestStatus,
+
+    /// The last commit of the pull request, if the forge told us.
+    pub head_commit_oid: Option<String>,
+
+    pub labels: Vec<String>,
+    pub milestone: Option<String>,
+    pub merged_by: Option<String>,
+
+    /// When the pull request was merged, in ISO 8601 format.
+    pub merged_at: Option<String>,
+
+    /// The numbers of the issues the pull request says it closes.
+    pub closes: Vec<i64>,
+
+    /// For open pull requests, if the forge told us.
+    pub review: Option<ReviewStatus>,
+}
+
+impl PrInfo {
+    /// The branches the pull request is labelled to be backported
+    /// to, e.g. "release-23.11" for "backport release-23.11".
+    pub fn backport_branches(&self) -> Vec<&str> {
+        self.labels
+            .iter()
+            .filter_map(|label| label.strip_prefix("backport "))
+            .map(str::trim)
+            .collect()
+    }
+}
+
+/// A pull request that mentions another one.
+#[derive(Debug)]
+pub struct LinkedPr {
+    pub number: i64,
+    pub title: String,
+    pub url: String,
+    pub branch: String,
+    pub status: PullRequestStatus,
+
+    /// The pull request's description.
+    pub body: String,
+
+    /// The branch the pull request was made from.
+    pub head_branch: String,
+}
+
+static PR_REFERENCE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:#|/pull/)(\d+)\b").unwrap());
+
+static BACKPORT_KEYWORD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(?:backport(?:s|ed)?|cherry[- ]pick(?:s|ed)?)\b").unwrap());
+
+/// Whether `text` mentions pull request `number`, as "#<number>" or
+/// a link to it.
+fn references(text: &str, number: i64) -> bool {
+    let number = number.to_string();
+    PR_REFERENCE
+        .captures_iter(text)
+        .any(|captures| captures[1] == number)
+}
+
+impl LinkedPr {
+    /// Whether this pull request reverts pull request `number`,
+    /// titled `title`.  The "Revert" button on GitHub, and people
+    /// doing it by hand, title reverts 'Revert "<title>"'; if the
+    /// title has been changed since, the description should still
+    /// say which pull request is being reverted.
+    pub fn reverts(&self, number: i64, title: &str) -> bool {
+        if self.title == format!("Revert \"{}\"", title) {
+            return true;
+        }
+
+        self.title.starts_with("Revert ") && references(&self.body, number)
+    }
+
+    /// Whether this pull request backports pull request `number` to
+    /// its base branch.  Backports opened by the backport bot are
+    /// titled "[Backport <branch>] <title>" and made from a branch
+    /// named "backport-<number>-to-<branch>".  Those opened by hand
+    /// should at least say in the description that they cherry-pick
+    /// it.
+    pub fn backports(&self, number: i64) -> bool {
+        let title_prefix = format!("[Backport {}]", self.branch);
+        let head_branch = format!("backport-{}-to-{}", number, self.branch);
+        if self.title.starts_with(&title_prefix) || self.head_branch == head_branch {
+            return true;
+        }
+
+        // The reference has to come after the keyword, on the same
+        // line.
+        BACKPORT_KEYWORD.find_iter(&self.body).any(|keyword| {
+            let rest = &self.body[keyword.end()..];
+            let line = rest.split('\n').next().unwrap_or_default();
+            references(line, number)
+        })
+    }
+
+    /// The merge commit, if the pull request has been merged.
+    pub fn merge_commit(&self) -> Option<&str> {
+        match &self.status {
+            PullRequestStatus::Merged { merge_commit_oid } => merge_commit_oid.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// A merged pull request that reverted another one.
+#[derive(Debug)]
+pub struct Revert {
+    pub merge_commit: String,
+
+    /// The merge commits of merged pull requests that reverted the
+    /// revert, putting the change back.
+    pub relands: Vec<String>,
+}
+
+/// The merged pull request that a commit landed with.
+#[derive(Debug)]
+pub struct CommitPr {
+    pub number: i64,
+    pub title: String,
+    pub author: Option<String>,
+
+    /// The owner of the repository the pull request was merged from.
+    pub head_owner: Option<String>,
+}
+
+/// One of the commits in a pull request, as it was before it was
+/// merged.
+#[derive(Debug)]
+pub struct PrCommit {
+    pub oid: String,
+
+    /// The first line of the commit message.
+    pub headline: String,
+}
+
+// The keywords GitHub, GitLab and Gitea all understand as closing an
+// issue when a pull request is merged.  Issues in other repositories
+// (owner/repo#123) aren't included.
+static CLOSING_KEYWORD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:close[sd]?|fix(?:e[sd])?|resolve[sd]?):?\s+#(\d+)\b").unwrap()
+});
+
+/// The issue numbers a pull request's description says it closes.
+pub fn closing_issues(body: &str) -> Vec<i64> {
+    let mut issues: Vec<i64> = CLOSING_KEYWORD
+        .captures_iter(body)
+        .filter_map(|captures| captures[1].parse().ok())
+        .collect();
+    issues.sort_unstable();
+    issues.dedup();
+    issues
+}
+
+/// A service hosting the tracked repository, that can tell us about
+/// its pull requests (or merge requests, as some forges call them).
+#[async_trait]
+pub trait Forge: Send + Sync {
+    fn kind(&self) -> Kind;
+
+    /// Links to the repository's pages on the forge's website.
+    fn web_links(&self) -> WebLinks;
+
+    async fn pr_info(&self, number: i64) -> Result<PrInfo, Error>;
+
+    /// Search the repository's pull requests, using the forge's own
+    /// search syntax.
+    async fn search(&self, _query: &str, _limit: i64) -> Result<Vec<PrInfo>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// The merged pull request that `commit` landed with, for commits
+    /// that don't say in their messages, like those of rebase merges.
+    async fn commit_pr(&self, _commit: &str) -> Result<Option<CommitPr>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// The commits in pull request `number`, oldest first.
+    async fn commits(&self, _number: i64) -> Result<Vec<PrCommit>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// The pull requests that mention pull request `number`, among
+    /// which will be any reverts or backports of it.
+    async fn referencing_prs(&self, _number: i64) -> Result<Vec<LinkedPr>, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// The merged pull requests among `referencing`, the pull requests
+/// that mention pull request `number`, titled `title`, that revert
+/// it.  Reverting a revert mentions it the same way, so `forge` is
+/// asked what mentions each revert to find out if it was relanded.
+pub async fn reverts(
+    forge: &dyn Forge,
+    number: i64,
+    title: &str,
+    referencing: &[LinkedPr],
+) -> Result<Vec<Revert>, Error> {
+    let mut reverts = Vec::new();
+    for revert in referencing.iter().filter(|pr| pr.reverts(number, title)) {
+        let merge_commit = match revert.merge_commit() {
+            Some(commit) => commit.to_string(),
+            None => continue,
+        };
+
+        let relands = forge
+            .referencing_prs(revert.number)
+            .await?
+            .iter()
+            .filter(|pr| pr.reverts(revert.number, &revert.title))
+            .filter_map(LinkedPr::merge_commit)
+            .map(str::to_string)
+            .collect();
+
+        reverts.push(Revert {
+            merge_commit,
+            relands,
+        });
+    }
+
+    Ok(reverts)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+impl Kind {
+    /// The API URL to use if none is configured, for forges that
+    /// have a canonical public instance.
+    pub fn default_api_url(self) -> Option<&'static str> {
+        match self {
+            Self::GitHub => Some(crate::github::DOTCOM_API_URL),
+            Self::GitLab => Some("https://gitlab.com/api/v4"),
+            Self::Gitea => None,
+        }
+    }
+
+    /// The forge's website, given the URL of its API, which is
+    /// usually under /api on the same host.
+    fn web_url(self, api_url: &str) -> &str {
+        if api_url == crate::github::DOTCOM_API_URL {
+            return "https://github.com";
+        }
+
+        let api_url = api_url.trim_end_matches('/');
+        let suffix = match self {
+            Self::GitHub => "/api/v3",
+            Self::GitLab => "/api/v4",
+            Self::Gitea => "/api/v1",
+        };
+        api_url.strip_suffix(suffix).unwrap_or(api_url)
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(Self::GitHub),
+            "gitlab" => Ok(Self::GitLab),
+            // Forgejo is a fork of Gitea, and has the same API.
+            "gitea" | "forgejo" => Ok(Self::Gitea),
+            _ => Err(format!("unknown forge: {}", s)),
+        }
+    }
+}
+
+/// The repository to track, written like "NixOS/nixpkgs", or, to
+/// say which forge it's on, like "forgejo:example/nixpkgs".  GitLab
+/// projects can be in nested groups, so everything up to the last
+/// slash is the owner.
+#[derive(Debug)]
+pub struct Repository {
+    pub forge: Option<Kind>,
+    pub owner: String,
+    pub name: String,
+}
+
+impl FromStr for Repository {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (forge, path) = match s.split_once(':') {
+            Some((forge, path)) => (Some(forge.parse()?), path),
+            None => (None, s),
+        };
+
+        match path.rsplit_once('/') {
+            Some((owner, name)) if !owner.is_empty() && !name.is_empty() => Ok(Self {
+                forge,
+                owner: owner.to_string(),
+                name: name.to_string(),
+            }),
+            _ => Err(format!("expected [<forge>:]<owner>/<name>: {}", s)),
+        }
+    }
+}
+
+impl Display for Repository {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.owner, self.name)
+    }
+}
+
+/// Links to pages for a repository on its forge's website.
+#[derive(Debug)]
+pub struct WebLinks {
+    kind: Kind,
+
+    /// e.g. https://github.com/NixOS/nixpkgs.  For GitLab, this ends
+    /// in "/-", which GitLab puts before all of a project's pages.
+    repository_url: String,
+}
+
+impl WebLinks {
+    /// The links for `repository`, on the forge of kind `kind` whose
+    /// API is at `api_url`.
+    pub fn new(kind: Kind, api_url: &str, repository: &Repository) -> Self {
+        let mut repository_url = format!("{}/{}", kind.web_url(api_url), repository);
+        if let Kind::GitLab = kind {
+            repository_url.push_str("/-");
+        }
+
+        Self {
+            kind,
+            repository_url,
+        }
+    }
+
+    pub fn branch(&self, branch: &str) -> String {
+        match self.kind {
+            Kind::Gitea => format!("{}/src/branch/{}", self.repository_url, branch),
+            Kind::GitHub | Kind::GitLab => format!("{}/tree/{}", self.repository_url, branch),
+        }
+    }
+
+    pub fn commit(&self, commit: &str) -> String {
+        format!("{}/commit/{}", self.repository_url, commit)
+    }
+
+    /// The changes on `head` that aren't on `base`.
+    pub fn compare(&self, base: &str, head: &str) -> String {
+        format!("{}/compare/{}...{}", self.repository_url, base, head)
+    }
+
+    pub fn issue(&self, number: i64) -> String {
+        format!("{}/issues/{}", self.repository_url, number)
+    }
+}
+
+/// Build an Authorization header value, like "bearer <token>".
+pub async fn authorization_header(
+    scheme: &str,
+    token: &TokenProvider,
+    user_agent: &OsStr,
+) -> Result<HeaderValue, Error> {
+    let mut value = format!("{} ", scheme).into_bytes();
+    value.extend_from_slice(&token.token(user_agent).await?);
+    HeaderValue::from_bytes(value).map_err(Error::Request)
+}
+
+/// Make a GET request to a REST API, and deserialize the JSON
+/// response.  A 404 or 410 response is reported as `Error::NotFound`.
+pub async fn get_json<T: DeserializeOwned>(
+    url: &str,
+    authorization: HeaderValue,
+    user_agent: &OsStr,
+) -> Result<T, Error> {
+    let mut response = surf::get(url)
+        .header("Accept", "application/json")
+        .header(
+            "User-Agent",
+            HeaderValue::from_bytes(user_agent.as_bytes().to_vec()).map_err(Error::Request)?,
+        )
+        .header("Authorization", authorization)
+        .send()
+        .await
+        .map_err(Error::Request)?;
+
+    let status = response.status();
+    if status == StatusCode::NotFound || status == StatusCode::Gone {
+        return Err(Error::NotFound);
+    } else if !status.is_success() {
+        return Err(Error::Response(status));
+    }
+
+    response.body_json().await.map_err(Error::Deserialization)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closing() {
+        let body = "Fixes #12 and closes: #3.\nResolved #12, but not #4 or NixOS/nix#5.";
+        assert_eq!(closing_issues(body), vec![3, 12]);
+    }
+
+    #[test]
+    fn reverts() {
+        let revert = |title: &str, body: &str| LinkedPr {
+            number: 2,
+            title: title.to_string(),
+            url: String::new(),
+            branch: "master".to_string(),
+            status: PullRequestStatus::Open,
+            body: body.to_string(),
+            head_branch: "revert-1".to_string(),
+        };
+
+        assert!(revert("Revert \"foo: 1.0 -> 2.0\"", "").reverts(1, "foo: 1.0 -> 2.0"));
+        assert!(revert("Revert foo update", "Reverts NixOS/nixpkgs#1").reverts(1, "foo"));
+        assert!(!revert("Revert foo update", "Reverts NixOS/nixpkgs#12").reverts(1, "foo"));
+        assert!(!revert("Revert \"bar: 1.0 -> 2.0\"", "").reverts(1, "foo: 1.0 -> 2.0"));
+        assert!(!revert("foo: 2.0 -> 2.1", "Follow-up to #1").reverts(1, "foo"));
+    }
+
+    #[test]
+    fn backports() {
+        let backport = |title: &str, head_branch: &str, body: &str| LinkedPr {
+            number: 2,
+            title: title.to_string(),
+            url: String::new(),
+            branch: "release-23.11".to_string(),
+            status: PullRequestStatus::Open,
+            body: body.to_string(),
+            head_branch: head_branch.to_string(),
+        };
+
+        assert!(backport("[Backport release-23.11] foo: 1.0 -> 2.0", "b", "").backports(1));
+        assert!(backport("foo: 1.0 -> 2.0", "backport-1-to-release-23.11", "").backports(1));
+        assert!(backport("[23.11] foo: 1.0 -> 2.0", "b", "Cherry-picked from #1.").backports(1));
+        assert!(backport("[23.11] foo", "b", "Backport of NixOS/nixpkgs#1").backports(1));
+        assert!(!backport("[23.11] foo", "b", "Backport of #12").backports(1));
+        assert!(!backport("[Backport master] foo", "b", "").backports(1));
+        assert!(!backport("foo: fix build", "b", "Follow-up to #1").backports(1));
+    }
+
+    #[test]
+    fn repository() {
+        let repository: Repository = "NixOS/nixpkgs".parse().unwrap();
+        assert!(repository.forge.is_none());
+        assert_eq!(
+            (repository.owner.as_str(), repository.name.as_str()),
+            ("NixOS", "nixpkgs")
+        );
+
+        let repository: Repository = "gitlab:group/subgroup/project".parse().unwrap();
+        assert!(matches!(repository.forge, Some(Kind::GitLab)));
+        assert_eq!(repository.to_string(), "group/subgroup/project");
+
+        for invalid in &["nixpkgs", "NixOS/", "/nixpkgs", "svn:NixOS/nixpkgs"] {
+            assert!(invalid.parse::<Repository>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn web_links() {
+        let repository = "a/b".parse().unwrap();
+
+        let links = WebLinks::new(Kind::GitHub, "https://api.github.com", &repository);
+        assert_eq!(links.issue(1), "https://github.com/a/b/issues/1");
+
+        let links = WebLinks::new(Kind::GitHub, "https://git.example.com/api/v3", &repository);
+        assert_eq!(links.issue(1), "https://git.example.com/a/b/issues/1");
+
+        let links = WebLinks::new(Kind::Gitea, "https://codeberg.org/api/v1", &repository);
+        assert_eq!(
+            links.branch("main"),
+            "https://codeberg.org/a/b/src/branch/main"
+        );
+        assert_eq!(
+            links.compare("main", "abc"),
+            "https://codeberg.org/a/b/compare/main...abc"
+        );
+
+        let links = WebLinks::new(Kind::GitLab, "https://gitlab.com/api/v4", &repository);
+        assert_eq!(links.commit("abc"), "https://gitlab.com/a/b/-/commit/abc");
+    }
+}
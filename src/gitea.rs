@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Gitea, and Forgejo, which shares its API.
+
+use std::ffi::OsStr;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::auth::TokenProvider;
+use crate::forge::{
+    self, authorization_header, get_json, Error, Forge, PrInfo, PullRequestStatus, Repository,
+    WebLinks,
+};
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+    html_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Branch {
+    #[serde(rename = "ref")]
+    ref_: String,
+    sha: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Milestone {
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    number: i64,
+    title: String,
+    html_url: String,
+    user: Option<User>,
+    base: Branch,
+    head: Branch,
+    state: String,
+    merged: bool,
+    merge_commit_sha: Option<String>,
+    #[serde(default)]
+    labels: Vec<Label>,
+    body: Option<String>,
+    milestone: Option<Milestone>,
+    merged_by: Option<User>,
+    merged_at: Option<String>,
+}
+
+pub struct Gitea<'a> {
+    api_url: &'a str,
+    repository: &'a Repository,
+    token: &'a TokenProvider,
+    user_agent: &'a OsStr,
+}
+
+impl<'a> Gitea<'a> {
+    pub fn new(
+        api_url: &'a str,
+        repository: &'a Repository,
+        token: &'a TokenProvider,
+        user_agent: &'a OsStr,
+    ) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/'),
+            repository,
+            token,
+            user_agent,
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> Forge for Gitea<'a> {
+    fn kind(&self) -> forge::Kind {
+        forge::Kind::Gitea
+    }
+
+    fn web_links(&self) -> WebLinks {
+        WebLinks::new(self.kind(), self.api_url, self.repository)
+    }
+
+    async fn pr_info(&self, number: i64) -> Result<PrInfo, Error> {
+        let url = format!(
+            "{}/repos/{}/pulls/{}",
+            self.api_url, self.repository, number
+        );
+
+        let authorization = authorization_header("token", self.token, self.user_agent).await?;
+        let pr: PullRequest = get_json(&url, authorization, self.user_agent).await?;
+
+        let status = if pr.merged {
+            PullRequestStatus::Merged {
+                merge_commit_oid: pr.merge_commit_sha,
+            }
+        } else if pr.state == "closed" {
+            PullRequestStatus::Closed
+        } else {
+            PullRequestStatus::Open
+        };
+
+        let (author, author_url) = match pr.user {
+            Some(u) => (u.login, u.html_url),
+            None => (String::new(), None),
+        };
+
+        Ok(PrInfo {
+            number: pr.number,
+            branch: pr.base.ref_,
+            title: pr.title,
+            url: pr.html_url,
+            author,
+            author_url,
+            status,
+            head_commit_oid: pr.head.sha,
+            labels: pr.labels.into_iter().map(|label| label.name).collect(),
+            milestone: pr.milestone.map(|m| m.title),
+            merged_by: pr.merged_by.map(|u| u.login),
+            merged_at: pr.merged_at,
+            closes: forge::closing_issues(pr.body.as_deref().unwrap_or_default()),
+            review: None,
+        })
+    }
+}
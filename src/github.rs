@@ -3,14 +3,20 @@
 // SPDX-FileCopyrightText: 2021 Sumner Evans <me@sumnerevans.com>
 
 use std::ffi::OsStr;
-use std::fmt::{self, Display, Formatter};
 use std::os::unix::ffi::OsStrExt;
 
+use async_trait::async_trait;
 use graphql_client::GraphQLQuery;
 use serde::Deserialize;
 use surf::http::headers::HeaderValue;
 use surf::StatusCode;
 
+use crate::auth::TokenProvider;
+use crate::forge::{
+    self, authorization_header, get_json, CommitPr, Error, Forge, LinkedPr, PrCommit, PrInfo,
+    PullRequestStatus, Repository, ReviewStatus, WebLinks,
+};
+
 // ISO 8601 dates can be compared chronologically simply by comparing
 // them lexicographically, so representing them as strings and
 // comparing them as strings works just fine.  (As long as GitHub
@@ -19,29 +25,11 @@ type DateTime = String;
 
 type GitObjectID = String;
 
-#[derive(Debug)]
-pub enum Error {
-    NotFound,
-    Serialization(serde_json::Error),
-    Request(surf::Error),
-    Response(StatusCode),
-    Deserialization(http_types::Error),
-}
+type URI = String;
 
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        use Error::*;
-        match self {
-            NotFound => write!(f, "Not found"),
-            Serialization(e) => write!(f, "Serialization error: {}", e),
-            Request(e) => write!(f, "Request error: {}", e),
-            Response(s) => write!(f, "Unexpected response status: {}", s),
-            Deserialization(e) => write!(f, "Deserialization error: {}", e),
-        }
-    }
-}
-
-impl std::error::Error for Error {}
+/// The API URL for github.com, as opposed to a GitHub Enterprise
+/// Server instance.
+pub const DOTCOM_API_URL: &str = "https://api.github.com";
 
 // Prior to some time in October 2013, GitHub changes from showing the
 // GraphQL API us a fake merge commit that isn't actually reachable in
@@ -61,6 +49,11 @@ impl std::error::Error for Error {}
 // happens in March 2016 (we don't need to check for that by date).
 const FIRST_KNOWN_NULL_MERGE_COMMIT: &str = "2013-10-20T15:50:06Z";
 
+/// How many pages of 100 cross references to fetch for a PR.  Some
+/// PRs, like those updating widely used packages, are mentioned a
+/// lot, but not so much that it should take more than this.
+const MAX_REFERENCE_PAGES: usize = 10;
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "vendor/github_schema.graphql",
@@ -69,15 +62,166 @@ const FIRST_KNOWN_NULL_MERGE_COMMIT: &str = "2013-10-20T15:50:06Z";
 )]
 struct PrInfoQuery;
 
-type PullRequest = pr_info_query::PrInfoQueryRepositoryPullRequest;
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "vendor/github_schema.graphql",
+    query_path = "src/pr_search.graphql",
+    response_derives = "Debug"
+)]
+struct PrSearchQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "vendor/github_schema.graphql",
+    query_path = "src/pr_commits.graphql",
+    response_derives = "Debug"
+)]
+struct PrCommitsQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "vendor/github_schema.graphql",
+    query_path = "src/pr_references.graphql",
+    response_derives = "Debug"
+)]
+struct PrReferencesQuery;
+
+// PrInfoQuery and PrSearchQuery select the same pull request fields,
+// but graphql_client generates a separate type for each of them.
+macro_rules! impl_pull_request {
+    ($pull_request:ty) => {
+        impl $pull_request {
+            fn merge_commit_oid(&self) -> Option<&str> {
+                if self.merged_at.as_ref()?.as_str() < FIRST_KNOWN_NULL_MERGE_COMMIT {
+                    return None;
+                }
 
-impl PullRequest {
-    fn merge_commit_oid(&self) -> Option<&str> {
-        if self.merged_at.as_ref()?.as_str() < FIRST_KNOWN_NULL_MERGE_COMMIT {
-            return None;
+                Some(&self.merge_commit.as_ref()?.oid)
+            }
+
+            fn into_pr_info(self) -> PrInfo {
+                let status = if self.merged {
+                    let merge_commit_oid = self.merge_commit_oid().map(Into::into);
+                    PullRequestStatus::Merged { merge_commit_oid }
+                } else if self.closed {
+                    PullRequestStatus::Closed
+                } else {
+                    PullRequestStatus::Open
+                };
+
+                let (author, author_url) = match self.author {
+                    Some(a) => (a.login, Some(a.url)),
+                    None => (String::new(), None),
+                };
+
+                PrInfo {
+                    number: self.number,
+                    branch: self.base_ref_name,
+                    title: self.title,
+                    url: self.url,
+                    author,
+                    author_url,
+                    status,
+                    head_commit_oid: Some(self.head_ref_oid),
+                    labels: Vec::new(),
+                    milestone: None,
+                    merged_by: None,
+                    merged_at: self.merged_at,
+                    closes: Vec::new(),
+                    review: None,
+                }
+            }
         }
+    };
+}
 
-        Some(&self.merge_commit.as_ref()?.oid)
+impl_pull_request!(pr_info_query::PrInfoQueryRepositoryPullRequest);
+impl_pull_request!(pr_search_query::PrSearchQuerySearchNodesOnPullRequest);
+
+// Only PrInfoQuery asks for these, because they'd make searches much
+// more expensive.
+impl pr_info_query::PrInfoQueryRepositoryPullRequest {
+    fn labels(&self) -> Vec<String> {
+        self.labels
+            .iter()
+            .flat_map(|labels| labels.nodes.iter().flatten().flatten())
+            .map(|label| label.name.clone())
+            .collect()
+    }
+
+    fn review_status(&self) -> ReviewStatus {
+        use pr_info_query::PrInfoQueryRepositoryPullRequestCommitsNodesCommitStatusCheckRollupContextsNodes as Context;
+        use pr_info_query::{
+            CheckConclusionState, MergeableState, PullRequestReviewDecision,
+            PullRequestReviewState, StatusState,
+        };
+
+        let decision = self
+            .review_decision
+            .as_ref()
+            .map(|decision| match decision {
+                PullRequestReviewDecision::APPROVED => "approved".to_string(),
+                PullRequestReviewDecision::CHANGES_REQUESTED => "changes requested".to_string(),
+                PullRequestReviewDecision::REVIEW_REQUIRED => "review required".to_string(),
+                PullRequestReviewDecision::Other(other) => other.to_lowercase(),
+            });
+
+        let approvals = self
+            .latest_opinionated_reviews
+            .iter()
+            .flat_map(|reviews| reviews.nodes.iter().flatten().flatten())
+            .filter(|review| matches!(review.state, PullRequestReviewState::APPROVED))
+            .count();
+
+        let mut status = ReviewStatus {
+            draft: self.is_draft,
+            decision,
+            approvals,
+            conflicting: matches!(self.mergeable, MergeableState::CONFLICTING),
+            ..Default::default()
+        };
+
+        // The query only asks for the last commit, whose checks are
+        // the ones that matter.
+        let rollup = self
+            .commits
+            .nodes
+            .iter()
+            .flatten()
+            .flatten()
+            .find_map(|node| node.commit.status_check_rollup.as_ref());
+
+        if let Some(rollup) = rollup {
+            let checks = match rollup.state {
+                StatusState::SUCCESS => "passing",
+                StatusState::FAILURE | StatusState::ERROR => "failing",
+                _ => "pending",
+            };
+            status.checks = Some(checks.to_string());
+
+            status.failing_checks = rollup
+                .contexts
+                .nodes
+                .iter()
+                .flatten()
+                .flatten()
+                .filter_map(|context| match context {
+                    Context::CheckRun(run) => match run.conclusion {
+                        Some(CheckConclusionState::ACTION_REQUIRED)
+                        | Some(CheckConclusionState::FAILURE)
+                        | Some(CheckConclusionState::STARTUP_FAILURE)
+                        | Some(CheckConclusionState::TIMED_OUT) => Some(run.name.clone()),
+                        _ => None,
+                    },
+                    Context::StatusContext(status) => match status.state {
+                        StatusState::FAILURE | StatusState::ERROR => Some(status.context.clone()),
+                        _ => None,
+                    },
+                })
+                .collect();
+        }
+
+        status
     }
 }
 
@@ -86,50 +230,95 @@ struct GitHubGraphQLResponse<D> {
     data: D,
 }
 
-#[derive(Debug)]
-pub enum PullRequestStatus {
-    Open,
-    Closed,
-    Merged {
-        /// This field is optional because GitHub doesn't provide us with this information
-        /// for PRs merged before around March 2016.
-        merge_commit_oid: Option<String>,
-    },
+// The parts of the REST API's pull request objects that we need.
+// The REST API is only used where GraphQL doesn't have an
+// equivalent that's as simple to ask for.
+
+#[derive(Debug, Deserialize)]
+struct RestOwner {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestRepository {
+    owner: RestOwner,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestHead {
+    repo: Option<RestRepository>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestUser {
+    login: String,
 }
 
-#[derive(Debug)]
-pub struct PrInfo {
-    pub branch: String,
-    pub title: String,
-    pub author: String,
-    pub status: PullRequestStatus,
+#[derive(Debug, Deserialize)]
+struct RestPullRequest {
+    number: i64,
+    title: String,
+    user: Option<RestUser>,
+    head: RestHead,
+    merged_at: Option<String>,
 }
 
 pub struct GitHub<'a> {
-    token: &'a OsStr,
+    api_url: &'a str,
+    repository: &'a Repository,
+    token: &'a TokenProvider,
     user_agent: &'a OsStr,
 }
 
 impl<'a> GitHub<'a> {
-    pub fn new(token: &'a OsStr, user_agent: &'a OsStr) -> Self {
-        Self { token, user_agent }
+    pub fn new(
+        api_url: &'a str,
+        repository: &'a Repository,
+        token: &'a TokenProvider,
+        user_agent: &'a OsStr,
+    ) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/'),
+            repository,
+            token,
+            user_agent,
+        }
     }
 
-    fn authorization_header(&self) -> Result<HeaderValue, surf::Error> {
-        let mut value = b"bearer ".to_vec();
-        value.extend_from_slice(self.token.as_bytes());
-        Ok(HeaderValue::from_bytes(value)?)
+    fn is_dotcom(&self) -> bool {
+        self.api_url == DOTCOM_API_URL
     }
 
-    pub async fn pr_info_for_nixpkgs_pr(&self, pr: i64) -> Result<PrInfo, Error> {
-        let query = PrInfoQuery::build_query(pr_info_query::Variables {
-            owner: "NixOS".to_string(),
-            repo: "nixpkgs".to_string(),
-            number: pr,
-        });
+    // On github.com, the GraphQL endpoint is next to the REST API
+    // routes, but on GitHub Enterprise Server, the REST API is
+    // under /api/v3, and the GraphQL endpoint is /api/graphql.
+    fn graphql_url(&self) -> String {
+        match self.api_url.strip_suffix("/api/v3") {
+            Some(base) => format!("{}/api/graphql", base),
+            None => format!("{}/graphql", self.api_url),
+        }
+    }
 
-        let response = surf::post("https://api.github.com/graphql")
-            .header("Accept", "application/vnd.github.merge-info-preview+json")
+    // Preview media types are a github.com thing, and GitHub
+    // Enterprise Server versions that don't know about a particular
+    // preview may reject requests for it, so only ask for them from
+    // github.com.
+    fn accept_header(&self) -> &'static str {
+        if self.is_dotcom() {
+            "application/vnd.github.merge-info-preview+json"
+        } else {
+            "application/json"
+        }
+    }
+
+    async fn graphql<Q: GraphQLQuery>(
+        &self,
+        variables: Q::Variables,
+    ) -> Result<Q::ResponseData, Error> {
+        let query = Q::build_query(variables);
+
+        let response = surf::post(self.graphql_url())
+            .header("Accept", self.accept_header())
             .header(
                 "User-Agent",
                 HeaderValue::from_bytes(self.user_agent.as_bytes().to_vec())
@@ -137,7 +326,7 @@ impl<'a> GitHub<'a> {
             )
             .header(
                 "Authorization",
-                self.authorization_header().map_err(Error::Request)?,
+                authorization_header("bearer", self.token, self.user_agent).await?,
             )
             .body(serde_json::to_vec(&query).map_err(Error::Serialization)?)
             .send()
@@ -151,36 +340,199 @@ impl<'a> GitHub<'a> {
             return Err(Error::Response(status));
         }
 
-        let data: GitHubGraphQLResponse<pr_info_query::ResponseData> = dbg!(response)
-            .body_json()
-            .await
-            .map_err(Error::Deserialization)?;
+        let data: GitHubGraphQLResponse<Q::ResponseData> =
+            response.body_json().await.map_err(Error::Deserialization)?;
+
+        Ok(data.data)
+    }
+}
+
+#[async_trait]
+impl<'a> Forge for GitHub<'a> {
+    fn kind(&self) -> forge::Kind {
+        forge::Kind::GitHub
+    }
+
+    fn web_links(&self) -> WebLinks {
+        WebLinks::new(self.kind(), self.api_url, self.repository)
+    }
+
+    async fn pr_info(&self, pr: i64) -> Result<PrInfo, Error> {
+        let data = self
+            .graphql::<PrInfoQuery>(pr_info_query::Variables {
+                owner: self.repository.owner.clone(),
+                repo: self.repository.name.clone(),
+                number: pr,
+            })
+            .await?;
 
         let pr = data
-            .data
             .repository
             .and_then(|repo| repo.pull_request)
             .ok_or(Error::NotFound)?;
 
-        let status = if pr.merged {
-            let merge_commit_oid = pr.merge_commit_oid().map(Into::into);
-            PullRequestStatus::Merged { merge_commit_oid }
-        } else if pr.closed {
-            PullRequestStatus::Closed
-        } else {
-            PullRequestStatus::Open
-        };
+        let labels = pr.labels();
+        let milestone = pr.milestone.as_ref().map(|m| m.title.clone());
+        let merged_by = pr.merged_by.as_ref().map(|actor| actor.login.clone());
+        let closes = forge::closing_issues(&pr.body);
+        let review = pr.review_status();
 
-        let author = match pr.author {
-            Some(a) => a.login,
-            None => String::new(),
-        };
+        let mut info = pr.into_pr_info();
+        info.labels = labels;
+        info.milestone = milestone;
+        info.merged_by = merged_by;
+        info.closes = closes;
+        if matches!(info.status, PullRequestStatus::Open) {
+            info.review = Some(review);
+        }
+
+        Ok(info)
+    }
+
+    async fn search(&self, query: &str, limit: i64) -> Result<Vec<PrInfo>, Error> {
+        use pr_search_query::PrSearchQuerySearchNodes;
+
+        let data = self
+            .graphql::<PrSearchQuery>(pr_search_query::Variables {
+                query: format!("repo:{} is:pr {}", self.repository, query),
+                first: limit,
+            })
+            .await?;
+
+        Ok(data
+            .search
+            .nodes
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .filter_map(|node| match node {
+                PrSearchQuerySearchNodes::PullRequest(pr) => Some(pr.into_pr_info()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // This is the REST equivalent of a commit's
+    // associatedPullRequests.  A commit can be in more than one PR,
+    // but only one of them can have been merged with it.
+    async fn commit_pr(&self, commit: &str) -> Result<Option<CommitPr>, Error> {
+        let url = format!(
+            "{}/repos/{}/commits/{}/pulls",
+            self.api_url, self.repository, commit
+        );
+
+        let authorization = authorization_header("bearer", self.token, self.user_agent).await?;
+        let prs: Vec<RestPullRequest> = get_json(&url, authorization, self.user_agent).await?;
+
+        Ok(prs
+            .into_iter()
+            .find(|pr| pr.merged_at.is_some())
+            .map(|pr| CommitPr {
+                number: pr.number,
+                title: pr.title,
+                author: pr.user.map(|user| user.login),
+                head_owner: pr.head.repo.map(|repo| repo.owner.login),
+            }))
+    }
+
+    // Only the last 100 commits are fetched, which is plenty for
+    // telling whether a PR was rebased.
+    async fn commits(&self, pr: i64) -> Result<Vec<PrCommit>, Error> {
+        let data = self
+            .graphql::<PrCommitsQuery>(pr_commits_query::Variables {
+                owner: self.repository.owner.clone(),
+                repo: self.repository.name.clone(),
+                number: pr,
+            })
+            .await?;
+
+        let pr = data
+            .repository
+            .and_then(|repo| repo.pull_request)
+            .ok_or(Error::NotFound)?;
+
+        Ok(pr
+            .commits
+            .nodes
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .map(|node| PrCommit {
+                oid: node.commit.oid,
+                headline: node.commit.message_headline,
+            })
+            .collect())
+    }
+
+    // These are the PRs that show up in the PR's timeline as cross
+    // references.  Reverts and backports, whether opened by a bot or
+    // by hand, mention the PR they're for, but so do plenty of other
+    // PRs.
+    async fn referencing_prs(&self, pr: i64) -> Result<Vec<LinkedPr>, Error> {
+        use pr_references_query::PrReferencesQueryRepositoryPullRequestTimelineItemsNodes as Node;
+        use pr_references_query::PrReferencesQueryRepositoryPullRequestTimelineItemsNodesOnCrossReferencedEventSource as Source;
+
+        let mut sources = Vec::new();
+        let mut cursor = None;
+        for _ in 0..MAX_REFERENCE_PAGES {
+            let data = self
+                .graphql::<PrReferencesQuery>(pr_references_query::Variables {
+                    owner: self.repository.owner.clone(),
+                    repo: self.repository.name.clone(),
+                    number: pr,
+                    cursor: cursor.take(),
+                })
+                .await?;
+
+            let pr = data
+                .repository
+                .and_then(|repo| repo.pull_request)
+                .ok_or(Error::NotFound)?;
+
+            let page_info = pr.timeline_items.page_info;
+            sources.extend(
+                pr.timeline_items
+                    .nodes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|node| match node {
+                        Node::CrossReferencedEvent(event) => match event.source {
+                            Source::PullRequest(source) => Some(source),
+                            _ => None,
+                        },
+                        _ => None,
+                    }),
+            );
+
+            if !page_info.has_next_page {
+                break;
+            }
+            cursor = page_info.end_cursor;
+        }
+
+        Ok(sources
+            .into_iter()
+            .map(|source| {
+                let status = if source.merged {
+                    let merge_commit_oid = source.merge_commit.map(|commit| commit.oid);
+                    PullRequestStatus::Merged { merge_commit_oid }
+                } else if source.closed {
+                    PullRequestStatus::Closed
+                } else {
+                    PullRequestStatus::Open
+                };
 
-        Ok(PrInfo {
-            branch: pr.base_ref_name,
-            title: pr.title,
-            author,
-            status,
-        })
+                LinkedPr {
+                    number: source.number,
+                    title: source.title,
+                    url: source.url,
+                    branch: source.base_ref_name,
+                    status,
+                    body: source.body,
+                    head_branch: source.head_ref_name,
+                }
+            })
+            .collect())
     }
 }
@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+use std::ffi::OsStr;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::auth::TokenProvider;
+use crate::forge::{
+    self, authorization_header, get_json, Error, Forge, PrInfo, PullRequestStatus, Repository,
+    WebLinks,
+};
+
+#[derive(Debug, Deserialize)]
+struct User {
+    username: String,
+    web_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Milestone {
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeRequest {
+    iid: i64,
+    title: String,
+    web_url: String,
+    author: Option<User>,
+    target_branch: String,
+    state: String,
+    sha: Option<String>,
+    merge_commit_sha: Option<String>,
+    squash_commit_sha: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+    description: Option<String>,
+    milestone: Option<Milestone>,
+    merged_by: Option<User>,
+    merged_at: Option<String>,
+}
+
+impl MergeRequest {
+    // Depending on the project's merge method, a merged MR might
+    // have a merge commit, a squash commit, both (in which case the
+    // merge commit contains the squash commit), or neither, if it was
+    // fast-forwarded, in which case its head is what landed.
+    fn merge_commit_oid(&self) -> Option<String> {
+        self.merge_commit_sha
+            .as_ref()
+            .or_else(|| self.squash_commit_sha.as_ref())
+            .or_else(|| self.sha.as_ref())
+            .cloned()
+    }
+}
+
+pub struct GitLab<'a> {
+    api_url: &'a str,
+    project: &'a Repository,
+    token: &'a TokenProvider,
+    user_agent: &'a OsStr,
+}
+
+impl<'a> GitLab<'a> {
+    pub fn new(
+        api_url: &'a str,
+        project: &'a Repository,
+        token: &'a TokenProvider,
+        user_agent: &'a OsStr,
+    ) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/'),
+            project,
+            token,
+            user_agent,
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> Forge for GitLab<'a> {
+    fn kind(&self) -> forge::Kind {
+        forge::Kind::GitLab
+    }
+
+    fn web_links(&self) -> WebLinks {
+        WebLinks::new(self.kind(), self.api_url, self.project)
+    }
+
+    async fn pr_info(&self, number: i64) -> Result<PrInfo, Error> {
+        // Projects can be identified by their full path instead of
+        // their numeric ID, as long as the path is URL-encoded.
+        let url = format!(
+            "{}/projects/{}/merge_requests/{}",
+            self.api_url,
+            self.project.to_string().replace('/', "%2F"),
+            number
+        );
+
+        let authorization = authorization_header("Bearer", self.token, self.user_agent).await?;
+        let mr: MergeRequest = get_json(&url, authorization, self.user_agent).await?;
+
+        let status = match mr.state.as_str() {
+            "merged" => PullRequestStatus::Merged {
+                merge_commit_oid: mr.merge_commit_oid(),
+            },
+            "closed" => PullRequestStatus::Closed,
+            // As well as "opened", this covers "locked", which is
+            // what an open merge request is while it's being merged.
+            _ => PullRequestStatus::Open,
+        };
+
+        let (author, author_url) = match mr.author {
+            Some(a) => (a.username, Some(a.web_url)),
+            None => (String::new(), None),
+        };
+
+        Ok(PrInfo {
+            number: mr.iid,
+            branch: mr.target_branch,
+            title: mr.title,
+            url: mr.web_url,
+            author,
+            author_url,
+            status,
+            head_commit_oid: mr.sha,
+            labels: mr.labels,
+            milestone: mr.milestone.map(|m| m.title),
+            merged_by: mr.merged_by.map(|u| u.username),
+            merged_at: mr.merged_at,
+            closes: forge::closing_issues(mr.description.as_deref().unwrap_or_default()),
+            review: None,
+        })
+    }
+}
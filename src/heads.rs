@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Remembering where each branch's head has been seen, so we can
+//! tell when it last moved, and whether it was rewritten rather than
+//! fast-forwarded.  This is only kept in memory, so it's forgotten
+//! whenever pr-tracker is restarted.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+use async_std::sync::Mutex;
+use async_std::task;
+use once_cell::sync::Lazy;
+
+use crate::nixpkgs::{Head, Nixpkgs};
+
+/// How many of a branch's previous heads to remember.
+const MAX_HISTORY: usize = 20;
+
+/// How often to look at where the branches are.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+struct Seen {
+    /// The commits the head has been seen at, most recent last.
+    history: VecDeque<String>,
+
+    /// When the head was first seen at its current commit, if it was
+    /// seen somewhere else before that.
+    moved: Option<SystemTime>,
+
+    /// When the head was last seen to move to a commit that wasn't a
+    /// descendant of where it was before.
+    rewritten: Option<SystemTime>,
+}
+
+/// Where each branch's head has been seen.
+#[derive(Clone, Default)]
+pub struct Heads(BTreeMap<String, Seen>);
+
+static SEEN: Lazy<Mutex<Heads>> = Lazy::new(|| Mutex::new(Heads::default()));
+
+impl Heads {
+    /// Where `branch` was last seen.
+    fn current(&self, branch: &str) -> Option<&str> {
+        self.0.get(branch)?.history.back().map(String::as_str)
+    }
+
+    /// Record that `branch` has been seen at `commit`, which
+    /// `fast_forward` says is a descendant of where it was last seen.
+    fn record(&mut self, branch: &str, commit: &str, fast_forward: bool) {
+        let seen = self.0.entry(branch.to_string()).or_insert_with(|| Seen {
+            history: VecDeque::new(),
+            moved: None,
+            rewritten: None,
+        });
+
+        match seen.history.back() {
+            Some(last) if last == commit => return,
+            Some(_) => {
+                let now = SystemTime::now();
+                seen.moved = Some(now);
+                if !fast_forward {
+                    seen.rewritten = Some(now);
+                }
+            }
+            None => {}
+        }
+
+        if seen.history.len() == MAX_HISTORY {
+            seen.history.pop_front();
+        }
+        seen.history.push_back(commit.to_string());
+    }
+
+    /// Record where each of `heads` is, checking whether any of them
+    /// have been rewritten since they were last seen.
+    pub async fn observe(&mut self, nixpkgs: &Nixpkgs<'_>, heads: &[Head]) {
+        for head in heads {
+            let last = match self.current(&head.branch).map(str::to_string) {
+                Some(last) if last != head.commit => last,
+                _ => {
+                    self.record(&head.branch, &head.commit, true);
+                    continue;
+                }
+            };
+
+            // If the old head has been garbage collected, there's no
+            // way to tell, so give the branch the benefit of the doubt.
+            let fast_forward = match nixpkgs.is_ancestor(&last, &head.commit).await {
+                Ok(fast_forward) => fast_forward,
+                Err(e) => {
+                    eprintln!("pr-tracker: is_ancestor: {}", e);
+                    true
+                }
+            };
+
+            self.record(&head.branch, &head.commit, fast_forward);
+        }
+    }
+
+    /// When `branch` was last seen to move, if it has moved since we
+    /// started watching it.
+    pub fn last_moved(&self, branch: &str) -> Option<SystemTime> {
+        self.0.get(branch)?.moved
+    }
+
+    /// When `branch` was last seen to be rewritten, if it has been
+    /// since we started watching it.
+    pub fn last_rewritten(&self, branch: &str) -> Option<SystemTime> {
+        self.0.get(branch)?.rewritten
+    }
+
+    /// The commits `branch` has been seen at before its current head,
+    /// most recent first.
+    pub fn previous(&self, branch: &str) -> Vec<String> {
+        match self.0.get(branch) {
+            Some(seen) => seen.history.iter().rev().skip(1).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Look at where the branches are every so often, forever, so that
+/// the heads seen are only ever recorded in one place.
+pub async fn watch(nixpkgs: Nixpkgs<'_>) {
+    loop {
+        match nixpkgs.heads().await {
+            Ok(heads) => {
+                // Checking for rewrites runs git for every branch that
+                // moved, so do it on a copy rather than holding the
+                // lock the whole time.  This is the only place that
+                // writes to SEEN, so the copy can't go stale.
+                let mut seen = seen().await;
+                seen.observe(&nixpkgs, &heads).await;
+                *SEEN.lock().await = seen;
+            }
+            Err(e) => eprintln!("pr-tracker: heads: {}", e),
+        }
+
+        task::sleep(INTERVAL).await;
+    }
+}
+
+/// A copy of where the branches have been seen so far.
+pub async fn seen() -> Heads {
+    SEEN.lock().await.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moved() {
+        let mut heads = Heads::default();
+        heads.record("master", "a", true);
+        heads.record("master", "a", true);
+        assert_eq!(heads.last_moved("master"), None);
+
+        heads.record("master", "b", true);
+        let moved = heads.last_moved("master");
+        assert!(moved.is_some());
+        heads.record("master", "b", true);
+        assert_eq!(heads.last_moved("master"), moved);
+        assert_eq!(heads.last_rewritten("master"), None);
+    }
+
+    #[test]
+    fn rewritten() {
+        let mut heads = Heads::default();
+        heads.record("master", "a", true);
+        heads.record("master", "b", true);
+        heads.record("master", "c", false);
+        assert!(heads.last_rewritten("master").is_some());
+        assert_eq!(heads.previous("master"), vec!["b", "a"]);
+    }
+}
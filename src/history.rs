@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Finding the PRs in the local checkout's history, without asking
+//! the forge about each commit.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use futures_util::future::join_all;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::branches::{is_development_branch, DEVELOPMENT_BRANCH_PATTERN};
+use crate::forge::{self, CommitPr, Forge, PrCommit, PrInfo, PullRequestStatus};
+use crate::nixpkgs::{self, LogEntry, Nixpkgs};
+
+/// The most commits to look through when comparing branches.  Most
+/// commits aren't PR merges, so this needs to be much higher than
+/// the number of PRs we're looking for.
+const MAX_COMMITS: usize = 20_000;
+
+/// The most commits to ask the forge about, for commits whose
+/// messages don't say which PR they're from.  Each one is a separate
+/// request.
+const MAX_LOOKUPS: usize = 50;
+
+static MERGE_SUBJECT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\AMerge pull request #(\d+) from ([^/\s]+)/").unwrap());
+
+/// Merges of one development branch into another, like the merge of
+/// staging-next into master at the end of a staging cycle, which
+/// carry lots of other changes along with them, as a POSIX extended
+/// regular expression.  They're either merged through a PR, or
+/// directly, with Git's default message.
+static CARRY_SUBJECT: Lazy<String> = Lazy::new(|| {
+    format!(
+        "^Merge (pull request #[0-9]+ from [^/ ]+/{0}|branch '{0}'( into .*)?)$",
+        DEVELOPMENT_BRANCH_PATTERN
+    )
+});
+
+static SQUASH_SUBJECT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\A(.*) \(#(\d+)\)\z").unwrap());
+
+/// A change that landed on a branch, either by merging a PR, or by
+/// being pushed directly.
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub commit: String,
+    pub branch: String,
+    pub pr_number: Option<i64>,
+    pub title: String,
+
+    /// Who opened the PR, if the forge has been asked.
+    pub author: Option<String>,
+
+    /// The owner of the repository the PR was merged from, which is
+    /// usually, but not always, the PR's author.
+    pub head_owner: Option<String>,
+}
+
+impl Change {
+    pub fn from_log_entry(entry: LogEntry) -> Self {
+        let LogEntry {
+            commit,
+            branch,
+            subject,
+            body,
+        } = entry;
+
+        // GitHub's merge commits look like this:
+        //
+        //     Merge pull request #123 from alice/foo
+        //
+        //     foo: 1.0 -> 2.0
+        if let Some(captures) = MERGE_SUBJECT.captures(&subject) {
+            let title = body.lines().find(|line| !line.is_empty());
+            return Self {
+                commit,
+                branch,
+                pr_number: captures[1].parse().ok(),
+                title: title.unwrap_or_default().to_string(),
+                author: None,
+                head_owner: Some(captures[2].to_string()),
+            };
+        }
+
+        // And squash merges look like "foo: 1.0 -> 2.0 (#123)".
+        if let Some(captures) = SQUASH_SUBJECT.captures(&subject) {
+            return Self {
+                commit,
+                branch,
+                pr_number: captures[2].parse().ok(),
+                title: captures[1].to_string(),
+                author: None,
+                head_owner: None,
+            };
+        }
+
+        Self {
+            commit,
+            branch,
+            pr_number: None,
+            title: subject,
+            author: None,
+            head_owner: None,
+        }
+    }
+}
+
+/// A page of the changes that touched `path`, on any of the
+/// remote's development branches, newest first, not counting the
+/// merges that carried them from one branch to another.  Also
+/// returns whether there are any more pages after this one.
+pub async fn changes_to_path(
+    nixpkgs: &Nixpkgs<'_>,
+    path: &str,
+    page: usize,
+    per_page: usize,
+) -> Result<(Vec<Change>, bool), nixpkgs::Error> {
+    let branches: Vec<_> = nixpkgs
+        .branches()
+        .await?
+        .into_iter()
+        .filter(|branch| is_development_branch(branch))
+        .collect();
+
+    // Ask for one more than we need, to find out if there's another
+    // page.
+    let mut entries = nixpkgs
+        .first_parent_log(
+            &branches,
+            path,
+            &CARRY_SUBJECT,
+            page.saturating_mul(per_page),
+            per_page + 1,
+        )
+        .await?;
+
+    let more = entries.len() > per_page;
+    entries.truncate(per_page);
+
+    Ok((
+        entries.into_iter().map(Change::from_log_entry).collect(),
+        more,
+    ))
+}
+
+/// Ask `forge` which PRs `commits` landed with.  Commits it doesn't
+/// know about are left out.
+async fn commit_prs(forge: &dyn Forge, commits: &[&str]) -> BTreeMap<String, CommitPr> {
+    let results = join_all(commits.iter().map(|commit| forge.commit_pr(commit))).await;
+
+    let mut prs = BTreeMap::new();
+    for (commit, result) in commits.iter().zip(results) {
+        match result {
+            Ok(Some(pr)) => {
+                prs.insert(commit.to_string(), pr);
+            }
+            Ok(None) => {}
+            Err(forge::Error::Unsupported) => break,
+            Err(e) => eprintln!("pr-tracker: commit_pr: {}", e),
+        }
+    }
+    prs
+}
+
+/// The PRs that have landed on `from`, but haven't made it to `to`
+/// yet, newest first.  Also returns whether there were more than
+/// `limit` of them.
+pub async fn prs_between(
+    forge: &dyn Forge,
+    nixpkgs: &Nixpkgs<'_>,
+    from: &str,
+    to: &str,
+    limit: usize,
+) -> Result<(Vec<Change>, bool), nixpkgs::Error> {
+    let mut changes: Vec<_> = nixpkgs
+        .log_range(from, to, MAX_COMMITS)
+        .await?
+        .into_iter()
+        .map(Change::from_log_entry)
+        .collect();
+
+    // Rebase merges put a PR's commits straight onto the branch,
+    // without anything in their messages to say which PR they were
+    // from, so the forge has to be asked.  The commits inside merged
+    // PRs aren't on the first-parent history, so they're left alone.
+    let direct: BTreeSet<_> = nixpkgs
+        .first_parent_range(from, to, MAX_COMMITS)
+        .await?
+        .into_iter()
+        .map(|entry| entry.commit)
+        .collect();
+    let unknown: Vec<_> = changes
+        .iter()
+        .filter(|change| change.pr_number.is_none() && direct.contains(&change.commit))
+        .map(|change| change.commit.as_str())
+        .take(MAX_LOOKUPS)
+        .collect();
+    let mut prs = commit_prs(forge, &unknown).await;
+    for change in changes.iter_mut() {
+        if let Some(pr) = prs.remove(&change.commit) {
+            change.pr_number = Some(pr.number);
+            change.title = pr.title;
+            change.author = pr.author;
+            change.head_owner = pr.head_owner;
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut changes: Vec<_> = changes
+        .into_iter()
+        .filter(|change| match change.pr_number {
+            // A PR's commits can be cherry-picked, so the same PR
+            // number can turn up more than once.
+            Some(number) => seen.insert(number),
+            None => false,
+        })
+        .take(limit + 1)
+        .collect();
+
+    let more = changes.len() > limit;
+    changes.truncate(limit);
+
+    // Neither kind of merge commit says who opened the PR, so that
+    // has to come from the forge too, for as many as it's asked.
+    let unknown: Vec<_> = changes
+        .iter()
+        .filter(|change| change.author.is_none())
+        .map(|change| change.commit.as_str())
+        .take(MAX_LOOKUPS)
+        .collect();
+    let mut prs = commit_prs(forge, &unknown).await;
+    for change in changes.iter_mut() {
+        if let Some(pr) = prs.remove(&change.commit) {
+            change.author = pr.author;
+        }
+    }
+
+    Ok((changes, more))
+}
+
+/// Find the merge commit of a PR in the local checkout, if it was
+/// merged but the forge didn't say where, as GitHub doesn't for PRs
+/// merged before around March 2016.  The merge is found by the
+/// subject GitHub gives merge commits on the base branch's
+/// first-parent history, or failing that, by the PR's head commit
+/// being merged into the base branch.
+pub async fn resolve_merge_commit(
+    nixpkgs: &Nixpkgs<'_>,
+    pr: &mut PrInfo,
+) -> Result<(), nixpkgs::Error> {
+    let merge_commit_oid = match &mut pr.status {
+        PullRequestStatus::Merged { merge_commit_oid } if merge_commit_oid.is_none() => {
+            merge_commit_oid
+        }
+        _ => return Ok(()),
+    };
+
+    // The " from" stops #12 from matching #123.
+    let text = format!("Merge pull request #{} from", pr.number);
+    let number = pr.number.to_string();
+    *merge_commit_oid = nixpkgs
+        .first_parent_grep(&pr.branch, &text)
+        .await?
+        .into_iter()
+        .find(|entry| match MERGE_SUBJECT.captures(&entry.subject) {
+            Some(captures) => captures[1] == number,
+            None => false,
+        })
+        .map(|entry| entry.commit);
+
+    if merge_commit_oid.is_none() {
+        if let Some(head) = &pr.head_commit_oid {
+            *merge_commit_oid = nixpkgs.merge_of(&pr.branch, head).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The commits that landed when a PR was merged at `merge_commit`.
+/// For a rebase merge, that's the rebased counterparts of each of
+/// `commits`, ending with `merge_commit`.  Otherwise it's just
+/// `merge_commit`, which is either a merge of all of the PR's
+/// commits, or a squash of them.
+pub async fn landed_commits(
+    nixpkgs: &Nixpkgs<'_>,
+    merge_commit: &str,
+    commits: &[PrCommit],
+) -> Result<Vec<String>, nixpkgs::Error> {
+    let merge_commit_only = vec![merge_commit.to_string()];
+
+    if commits.len() < 2 || nixpkgs.parents(merge_commit).await?.len() != 1 {
+        return Ok(merge_commit_only);
+    }
+
+    // Rebasing changes commits' IDs, but keeps their messages, so
+    // the PR was rebased if the commits leading up to the merge
+    // commit have the same subjects as the PR's commits.
+    let landed = nixpkgs
+        .first_parent_subjects(merge_commit, commits.len())
+        .await?;
+    let rebased = landed
+        .iter()
+        .rev()
+        .map(|(_, subject)| subject)
+        .eq(commits.iter().map(|commit| &commit.headline));
+
+    if !rebased {
+        return Ok(merge_commit_only);
+    }
+
+    Ok(landed.into_iter().map(|(commit, _)| commit).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(subject: &str, body: &str) -> LogEntry {
+        LogEntry {
+            commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            branch: "master".to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let change = Change::from_log_entry(entry(
+            "Merge pull request #123 from alice/foo",
+            "foo: 1.0 -> 2.0\n",
+        ));
+        assert_eq!(change.pr_number, Some(123));
+        assert_eq!(change.title, "foo: 1.0 -> 2.0");
+        assert_eq!(change.head_owner.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn squash() {
+        let change = Change::from_log_entry(entry("foo: 1.0 -> 2.0 (#123)", ""));
+        assert_eq!(change.pr_number, Some(123));
+        assert_eq!(change.title, "foo: 1.0 -> 2.0");
+        assert_eq!(change.head_owner, None);
+    }
+
+    #[test]
+    fn direct() {
+        let change = Change::from_log_entry(entry("foo: 1.0 -> 2.0", ""));
+        assert_eq!(change.pr_number, None);
+        assert_eq!(change.title, "foo: 1.0 -> 2.0");
+    }
+}
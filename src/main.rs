@@ -2,15 +2,33 @@
 // SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
 // SPDX-FileCopyrightText: 2021 Sumner Evans <me@sumnerevans.com>
 
+mod auth;
 mod branches;
+mod cache;
+mod channels;
+mod compare;
+mod export;
+mod forge;
+mod gitea;
 mod github;
+mod gitlab;
+mod heads;
+mod history;
 mod nixpkgs;
+mod og_image;
 mod ogmeta;
+mod overview;
+mod packages;
+mod releases;
 mod systemd;
+#[cfg(test)]
+mod tests;
 mod tree;
+mod watch;
 
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use askama::Template;
 use async_std::io;
@@ -22,13 +40,25 @@ use async_std::prelude::*;
 use async_std::process::exit;
 use futures_util::future::join_all;
 use http_types::mime;
+use http_types::url::Url;
 use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::Deserialize;
+use serde_json::json;
 use structopt::StructOpt;
+use tide::http::cookies::Key;
 use tide::{Request, Response};
 
-use github::{GitHub, PullRequestStatus};
+use auth::{GitHubApp, TokenProvider};
+use cache::Cache;
+use channels::Channels;
+use forge::{Forge, PullRequestStatus, ReviewStatus, WebLinks};
+use gitea::Gitea;
+use github::GitHub;
+use gitlab::GitLab;
+use heads::Heads;
 use nixpkgs::Nixpkgs;
+use releases::{EndOfLife, Releases};
 use systemd::{is_socket_inet, is_socket_unix, listen_fds};
 use tree::Tree;
 use ogmeta::Ogmeta;
@@ -49,11 +79,66 @@ struct Config {
 
     #[structopt(long, default_value = "/")]
     mount: String,
+
+    #[structopt(long)]
+    public_url: Option<String>,
+
+    #[structopt(long, default_value = "github")]
+    forge: forge::Kind,
+
+    #[structopt(long, default_value = "NixOS/nixpkgs")]
+    repository: forge::Repository,
+
+    #[structopt(long)]
+    api_url: Option<String>,
+
+    #[structopt(long)]
+    channels_url: Option<String>,
+
+    #[structopt(long)]
+    hydra_url: Option<String>,
+
+    #[structopt(long)]
+    end_of_life: Vec<EndOfLife>,
+
+    #[structopt(long, parse(from_os_str))]
+    cookie_key: Option<PathBuf>,
+
+    #[structopt(long, requires_all = &["github-app-key", "github-app-installation"])]
+    github_app_id: Option<String>,
+
+    #[structopt(long, parse(from_os_str), requires = "github-app-id")]
+    github_app_key: Option<PathBuf>,
+
+    #[structopt(long, requires = "github-app-id")]
+    github_app_installation: Option<String>,
+}
+
+impl Config {
+    /// The forge the repository is on, which the repository can
+    /// override --forge for.
+    fn forge(&self) -> forge::Kind {
+        self.repository.forge.unwrap_or(self.forge)
+    }
 }
 
 static CONFIG: Lazy<Config> = Lazy::new(Config::from_args);
 
-static GITHUB_TOKEN: Lazy<OsString> = Lazy::new(|| {
+static RELEASES: Lazy<Releases> = Lazy::new(|| Releases::new(&CONFIG.end_of_life));
+
+static API_URL: Lazy<&str> = Lazy::new(|| {
+    let forge = CONFIG.forge();
+    match (&CONFIG.api_url, forge.default_api_url()) {
+        (Some(api_url), _) => api_url.as_str(),
+        (None, Some(api_url)) => api_url,
+        (None, None) => {
+            eprintln!("pr-tracker: --api-url is required for {:?}", forge);
+            exit(64)
+        }
+    }
+});
+
+fn read_token_from_stdin() -> OsString {
     use std::io::{stdin, BufRead, BufReader};
     use std::os::unix::prelude::*;
 
@@ -66,27 +151,214 @@ static GITHUB_TOKEN: Lazy<OsString> = Lazy::new(|| {
         bytes.pop();
     }
     OsString::from_vec(bytes)
+}
+
+fn github_app(id: &str, key_path: &Path, installation_id: &str) -> GitHubApp {
+    let pem = match std::fs::read(key_path) {
+        Ok(pem) => pem,
+        Err(e) => {
+            eprintln!("pr-tracker: {}: {}", key_path.display(), e);
+            exit(74)
+        }
+    };
+
+    let key = match jsonwebtoken::EncodingKey::from_rsa_pem(&pem) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("pr-tracker: {}: {}", key_path.display(), e);
+            exit(65)
+        }
+    };
+
+    GitHubApp::new(&API_URL, id.to_string(), key, installation_id.to_string())
+}
+
+static TOKEN: Lazy<TokenProvider> = Lazy::new(|| {
+    match (
+        &CONFIG.github_app_id,
+        &CONFIG.github_app_key,
+        &CONFIG.github_app_installation,
+    ) {
+        (Some(id), Some(key_path), Some(installation_id)) => {
+            if !matches!(CONFIG.forge(), forge::Kind::GitHub) {
+                eprintln!("pr-tracker: GitHub App authentication requires --forge github");
+                exit(64)
+            }
+
+            TokenProvider::App(github_app(id, key_path, installation_id))
+        }
+        _ => TokenProvider::Static(read_token_from_stdin()),
+    }
+});
+
+/// The key cookies are signed with.  Without a configured key, a new
+/// one is made each time pr-tracker starts, and cookies from before
+/// then are ignored.
+static COOKIE_KEY: Lazy<Key> = Lazy::new(|| {
+    let path = match &CONFIG.cookie_key {
+        Some(path) => path,
+        None => return Key::generate(),
+    };
+
+    let key = match std::fs::read(path) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("pr-tracker: {}: {}", path.display(), e);
+            exit(74)
+        }
+    };
+
+    if key.len() < 32 {
+        eprintln!(
+            "pr-tracker: {}: cookie key must be at least 32 bytes",
+            path.display()
+        );
+        exit(65)
+    }
+
+    Key::derive_from(&key)
 });
 
+/// The absolute path of `path`, relative to the mount point.
+fn route(path: &str) -> String {
+    let mut route = format!("/{}", CONFIG.mount.trim_matches('/'));
+    if !path.is_empty() {
+        if !route.ends_with('/') {
+            route.push('/');
+        }
+        route.push_str(path);
+    }
+    route
+}
+
+fn forge() -> Box<dyn Forge> {
+    let api_url = *API_URL;
+    let repository = &CONFIG.repository;
+    let user_agent = &CONFIG.user_agent;
+
+    match CONFIG.forge() {
+        forge::Kind::GitHub => Box::new(GitHub::new(api_url, repository, &TOKEN, user_agent)),
+        forge::Kind::GitLab => Box::new(GitLab::new(api_url, repository, &TOKEN, user_agent)),
+        forge::Kind::Gitea => Box::new(Gitea::new(api_url, repository, &TOKEN, user_agent)),
+    }
+}
+
 #[derive(Debug, Default, Template)]
 #[template(path = "page.html")]
 struct PageTemplate {
     error: Option<String>,
     pr_number: Option<String>,
+    pr_url: Option<String>,
     pr_title: Option<String>,
     pr_author: Option<String>,
+    pr_author_url: Option<String>,
+    labels: Vec<String>,
+    milestone: Option<String>,
+    merged_by: Option<String>,
+    merged_at: Option<String>,
+    closes: Vec<IssueLink>,
+
+    /// The release branches the PR is labelled to be backported to.
+    backports: Vec<String>,
+
+    review: Option<ReviewStatus>,
     closed: bool,
     tree: Option<Tree>,
     ogmeta: Option<Ogmeta>,
+    og_image: Option<String>,
+    search: Option<String>,
+    path: Option<String>,
+    attr: Option<String>,
+    results: Vec<SearchResult>,
+    prev_page: Option<String>,
+    next_page: Option<String>,
+    watch_url: String,
     source_url: String,
 }
 
+#[derive(Debug)]
+struct IssueLink {
+    number: i64,
+    url: String,
+}
+
+impl IssueLink {
+    fn new(links: &WebLinks, number: i64) -> Self {
+        Self {
+            number,
+            url: links.issue(number),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SearchResult {
+    number: Option<i64>,
+
+    /// The commit, for changes that were pushed without a PR.
+    commit: Option<String>,
+
+    title: String,
+    author: Option<String>,
+
+    /// Where a change to a path was merged from, when there's no PR
+    /// to say who its author was.
+    head_owner: Option<String>,
+
+    closed: bool,
+    summary: Option<Ogmeta>,
+}
+
+/// The maximum number of search results to show.  We have to work
+/// out the tree for each of them, so this can't be very high.
+const SEARCH_RESULTS: i64 = 10;
+
+/// The number of changes to a path to show on each page, for the
+/// same reason.
+const CHANGES_PER_PAGE: usize = 10;
+
+/// Pages of changes to paths, keyed by path and page number.  Paths
+/// come from requests, so there could be any number of them.
+static CHANGES: Lazy<Cache<(String, usize), (Vec<SearchResult>, bool)>> =
+    Lazy::new(|| Cache::new(Duration::from_secs(5 * 60), 1024));
+
 #[derive(Debug, Deserialize)]
 struct Query {
     pr: Option<String>,
+    q: Option<String>,
+    path: Option<String>,
+    attr: Option<String>,
+    page: Option<usize>,
+    format: Option<String>,
+    color: Option<String>,
 }
 
-async fn track_pr(pr_number: Option<String>, status: &mut u16, page: &mut PageTemplate) {
+/// Everything tracking a PR needs, other than the PR itself.
+#[derive(Clone, Copy)]
+struct Context<'a> {
+    forge: &'a dyn Forge,
+    nixpkgs: &'a Nixpkgs<'a>,
+    channels: Option<&'a Channels<'a>>,
+    hydra_url: Option<&'a str>,
+    releases: &'a Releases,
+    heads: &'a Heads,
+}
+
+async fn track_pr(
+    context: Context<'_>,
+    pr_number: Option<String>,
+    status: &mut u16,
+    page: &mut PageTemplate,
+) {
+    let Context {
+        forge,
+        nixpkgs,
+        channels,
+        hydra_url,
+        releases,
+        heads,
+    } = context;
+
     let pr_number = match pr_number {
         Some(pr_number) => pr_number,
         None => return,
@@ -101,10 +373,8 @@ async fn track_pr(pr_number: Option<String>, status: &mut u16, page: &mut PageTe
         }
     };
 
-    let github = GitHub::new(&GITHUB_TOKEN, &CONFIG.user_agent);
-
-    let pr_info = match github.pr_info_for_nixpkgs_pr(pr_number_i64).await {
-        Err(github::Error::NotFound) => {
+    let mut pr_info = match forge.pr_info(pr_number_i64).await {
+        Err(forge::Error::NotFound) => {
             *status = 404;
             page.error = Some(format!("No such nixpkgs PR #{}.", pr_number_i64));
             return;
@@ -119,25 +389,136 @@ async fn track_pr(pr_number: Option<String>, status: &mut u16, page: &mut PageTe
         Ok(info) => info,
     };
 
+    if let Err(e) = history::resolve_merge_commit(nixpkgs, &mut pr_info).await {
+        eprintln!("pr-tracker: resolve_merge_commit: {}", e);
+    }
+
+    page.backports = pr_info
+        .backport_branches()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let links = forge.web_links();
+    page.closes = pr_info
+        .closes
+        .iter()
+        .map(|number| IssueLink::new(&links, *number))
+        .collect();
+
     page.pr_number = Some(pr_number);
+    page.pr_url = Some(pr_info.url);
     page.pr_title = Some(pr_info.title);
     page.pr_author = Some(pr_info.author);
+    page.pr_author_url = pr_info.author_url;
+    page.labels = pr_info.labels;
+    page.milestone = pr_info.milestone;
+    page.merged_by = pr_info.merged_by;
+    page.merged_at = pr_info.merged_at;
+    page.review = pr_info.review;
 
     if matches!(pr_info.status, PullRequestStatus::Closed) {
         page.closed = true;
         return;
     }
 
-    let nixpkgs = Nixpkgs::new(&CONFIG.path, &CONFIG.remote);
-    let tree = Tree::make(pr_info.branch.to_string(), &pr_info.status, &nixpkgs).await;
+    let base_branch = pr_info.branch.to_string();
+    let tree = Tree::make(base_branch, &pr_info.status, releases, nixpkgs).await;
+    let mut tree = match tree {
+        Ok(tree) => tree,
+        Err(e) => {
+            *status = 500;
+            page.error = Some(e.to_string());
+            return;
+        }
+    };
+
+    // Reverts and backports are both among the PRs that mention this
+    // one, so they're only asked for once.
+    let merged = matches!(pr_info.status, PullRequestStatus::Merged { .. });
+    let referencing = if merged || !page.backports.is_empty() {
+        match forge.referencing_prs(pr_number_i64).await {
+            Ok(prs) => prs,
+            Err(forge::Error::Unsupported) => Vec::new(),
+            Err(e) => {
+                eprintln!("pr-tracker: referencing_prs: {}", e);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    if let PullRequestStatus::Merged {
+        merge_commit_oid: Some(merge_commit),
+    } = &pr_info.status
+    {
+        if let Err(e) = tree.find_packages(merge_commit, nixpkgs).await {
+            eprintln!("pr-tracker: find_packages: {}", e);
+        }
+
+        let commits = match forge.commits(pr_number_i64).await {
+            Ok(commits) => commits,
+            Err(forge::Error::Unsupported) => Vec::new(),
+            Err(e) => {
+                eprintln!("pr-tracker: commits: {}", e);
+                Vec::new()
+            }
+        };
+
+        match history::landed_commits(nixpkgs, merge_commit, &commits).await {
+            Ok(landed) => {
+                if let Err(e) = tree.find_partial(&landed, nixpkgs).await {
+                    eprintln!("pr-tracker: find_partial: {}", e);
+                }
+            }
+            Err(e) => eprintln!("pr-tracker: landed_commits: {}", e),
+        }
+
+        let title = page.pr_title.as_deref().unwrap_or_default();
+        let reverts = match forge::reverts(forge, pr_number_i64, title, &referencing).await {
+            Ok(reverts) => reverts,
+            Err(e) => {
+                eprintln!("pr-tracker: reverts: {}", e);
+                Vec::new()
+            }
+        };
+
+        if let Err(e) = tree.find_reverts(merge_commit, reverts, nixpkgs).await {
+            eprintln!("pr-tracker: find_reverts: {}", e);
+        }
+
+        tree.find_rewrites(merge_commit, heads, nixpkgs).await;
+
+        if let Err(e) = tree.find_carriers(merge_commit, hydra_url, nixpkgs).await {
+            eprintln!("pr-tracker: find_carriers: {}", e);
+        }
+
+        if let Some(channels) = channels {
+            tree.find_releases(merge_commit, channels, nixpkgs).await;
+        }
+    }
+
+    tree.find_links(&links, &pr_info.status, nixpkgs).await;
+
+    if !page.backports.is_empty() {
+        let prs: Vec<_> = referencing
+            .into_iter()
+            .filter(|pr| pr.backports(pr_number_i64))
+            .collect();
+
+        let branches: Vec<_> = page.backports.iter().map(String::as_str).collect();
+        tree.add_backports(&branches, &prs, &links, releases, nixpkgs)
+            .await;
+    }
+
     let ogmeta = Ogmeta::from_tree(&tree);
 
-    if let github::PullRequestStatus::Merged {
+    if let PullRequestStatus::Merged {
         merge_commit_oid, ..
     } = pr_info.status
     {
         if merge_commit_oid.is_none() {
-            page.error = Some("For older PRs, GitHub doesn't tell us the merge commit, so we're unable to track this PR past being merged.".to_string());
+            page.error = Some("For older PRs, GitHub doesn't tell us the merge commit, and we couldn't find it ourselves, so we're unable to track this PR past being merged.".to_string());
         }
     }
 
@@ -145,21 +526,433 @@ async fn track_pr(pr_number: Option<String>, status: &mut u16, page: &mut PageTe
     page.ogmeta = Some(ogmeta);
 }
 
+async fn search_prs(
+    forge: &dyn Forge,
+    nixpkgs: &Nixpkgs<'_>,
+    query: Option<String>,
+    status: &mut u16,
+    page: &mut PageTemplate,
+) {
+    let query = match query {
+        Some(query) if !query.trim().is_empty() => query,
+        _ => return,
+    };
+
+    let prs = match forge.search(&query, SEARCH_RESULTS).await {
+        Err(forge::Error::Unsupported) => {
+            *status = 501;
+            page.error = Some("Searching isn't supported for this repository.".to_string());
+            page.search = Some(query);
+            return;
+        }
+
+        Err(e) => {
+            *status = 500;
+            page.error = Some(e.to_string());
+            page.search = Some(query);
+            return;
+        }
+
+        Ok(prs) => prs,
+    };
+
+    if prs.is_empty() {
+        page.error = Some(format!("No PRs found matching “{}”.", query));
+    }
+
+    page.results = join_all(prs.into_iter().map(|mut pr| async move {
+        if let Err(e) = history::resolve_merge_commit(nixpkgs, &mut pr).await {
+            eprintln!("pr-tracker: resolve_merge_commit: {}", e);
+        }
+
+        let closed = matches!(pr.status, PullRequestStatus::Closed);
+
+        let summary = if closed {
+            None
+        } else {
+            match Tree::make(pr.branch.clone(), &pr.status, &RELEASES, nixpkgs).await {
+                Ok(tree) => Some(Ogmeta::from_tree(&tree)),
+                Err(e) => {
+                    eprintln!("pr-tracker: {}", e);
+                    None
+                }
+            }
+        };
+
+        SearchResult {
+            number: Some(pr.number),
+            commit: None,
+            title: pr.title,
+            author: Some(pr.author),
+            head_owner: None,
+            closed,
+            summary,
+        }
+    }))
+    .await;
+
+    page.search = Some(query);
+}
+
+/// A link to `page_number` of the current results, keeping the rest
+/// of the query the same.
+fn page_link(url: &Url, page_number: usize) -> String {
+    let query: Vec<_> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "page")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("page", &page_number.to_string());
+
+    format!("?{}", url.query().unwrap_or_default())
+}
+
+async fn changes_to_path(
+    nixpkgs: &Nixpkgs<'_>,
+    path: &str,
+    page_index: usize,
+) -> Result<(Vec<SearchResult>, bool), nixpkgs::Error> {
+    let key = (path.to_string(), page_index);
+    if let Some(cached) = CHANGES.get(&key) {
+        return Ok(cached);
+    }
+
+    let (changes, more) =
+        history::changes_to_path(nixpkgs, path, page_index, CHANGES_PER_PAGE).await?;
+
+    let results = join_all(changes.into_iter().map(|change| async move {
+        let status = PullRequestStatus::Merged {
+            merge_commit_oid: Some(change.commit.clone()),
+        };
+        let summary = match Tree::make(change.branch, &status, &RELEASES, nixpkgs).await {
+            Ok(tree) => Some(Ogmeta::from_tree(&tree)),
+            Err(e) => {
+                eprintln!("pr-tracker: {}", e);
+                None
+            }
+        };
+
+        SearchResult {
+            commit: match change.pr_number {
+                Some(_) => None,
+                None => Some(change.commit[..12].to_string()),
+            },
+            number: change.pr_number,
+            title: change.title,
+            author: None,
+            head_owner: change.head_owner,
+            closed: false,
+            summary,
+        }
+    }))
+    .await;
+
+    CHANGES.insert(key, (results.clone(), more));
+    Ok((results, more))
+}
+
+async fn track_path(
+    nixpkgs: &Nixpkgs<'_>,
+    url: &Url,
+    path: Option<String>,
+    attr: Option<String>,
+    page_number: Option<usize>,
+    status: &mut u16,
+    page: &mut PageTemplate,
+) {
+    let path = match (path, attr) {
+        (Some(path), _) if !path.trim().is_empty() => {
+            page.path = Some(path.clone());
+            path
+        }
+
+        (_, Some(attr)) if !attr.trim().is_empty() => {
+            let master = nixpkgs.branch_ref("master");
+            let path = match packages::path_for_attr(nixpkgs, master, attr.trim()).await {
+                Ok(Some(path)) => path,
+
+                Ok(None) => {
+                    *status = 404;
+                    page.error = Some(format!("Couldn't find the package “{}”.", attr));
+                    page.attr = Some(attr);
+                    return;
+                }
+
+                Err(e) => {
+                    *status = 500;
+                    page.error = Some(e.to_string());
+                    page.attr = Some(attr);
+                    return;
+                }
+            };
+
+            page.attr = Some(attr);
+            page.path = Some(path.clone());
+            path
+        }
+
+        _ => return,
+    };
+
+    // Git would take a leading colon to be pathspec magic.
+    if path.starts_with(':') {
+        *status = 400;
+        page.error = Some(format!("Invalid path: {}", path));
+        return;
+    }
+
+    // Pages are numbered from 1 in URLs.
+    let page_index = page_number.unwrap_or(1).saturating_sub(1);
+    if page_index.checked_mul(CHANGES_PER_PAGE).is_none() {
+        *status = 400;
+        page.error = Some(format!("Invalid page: {}", page_index + 1));
+        return;
+    }
+
+    let (results, more) = match changes_to_path(nixpkgs, &path, page_index).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            *status = 500;
+            page.error = Some(e.to_string());
+            return;
+        }
+    };
+
+    if results.is_empty() && page_index == 0 {
+        page.error = Some(format!("No changes found to {}.", path));
+    }
+
+    if page_index > 0 {
+        page.prev_page = Some(page_link(url, page_index));
+    }
+    if more {
+        page.next_page = Some(page_link(url, page_index + 2));
+    }
+
+    page.results = results;
+}
+
+const FORMATS: [&str; 5] = ["html", "text", "json", "dot", "mermaid"];
+
+/// Command line HTTP clients, which don't ask for any particular
+/// format, but whose users would much rather not read HTML.
+static CLI_USER_AGENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\A(curl|Wget|HTTPie)/").unwrap());
+
+/// Work out which format to respond in from the Accept header.  If
+/// HTML, plain text and JSON are all equally acceptable, HTML is
+/// preferred, except for command line clients.
+fn negotiate(accept: Option<&str>, user_agent: Option<&str>) -> &'static str {
+    let mut best = None;
+
+    for part in accept.unwrap_or_default().split(',') {
+        let mut params = part.split(';').map(str::trim);
+        let format = match params.next() {
+            Some("text/html") => "html",
+            Some("text/plain") => "text",
+            Some("application/json") => "json",
+            _ => continue,
+        };
+
+        let q = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse().ok())
+            .unwrap_or(1.0);
+
+        // Prefer the highest q, and then whichever came first.
+        let better = match best {
+            Some((_, best_q)) => q > best_q,
+            None => q > 0.0,
+        };
+        if better {
+            best = Some((format, q));
+        }
+    }
+
+    match (best, user_agent) {
+        (Some((format, _)), _) => format,
+        (None, Some(ua)) if CLI_USER_AGENT.is_match(ua) => "text",
+        (None, _) => "html",
+    }
+}
+
+/// Say that `response` depends on the headers `negotiate` looks at,
+/// so caches don't serve it in response to requests for another
+/// format.
+fn vary(mut response: Response) -> Response {
+    response.insert_header("Vary", "Accept, User-Agent");
+    response
+}
+
+fn plain_response(status: u16, body: String) -> Response {
+    Response::builder(status)
+        .content_type(mime::PLAIN)
+        .body(body)
+        .build()
+}
+
+fn json_response(status: u16, body: serde_json::Value) -> Response {
+    Response::builder(status)
+        .content_type(mime::JSON)
+        .body(body)
+        .build()
+}
+
+/// Respond with the tracked PR in `format`, rather than as a web
+/// page.
+fn export_tree(format: &str, mut status: u16, page: &PageTemplate, colour: bool) -> Response {
+    if !FORMATS.contains(&format) {
+        return plain_response(400, format!("Unknown format: {}\n", format));
+    }
+
+    let pr_number = match &page.pr_number {
+        Some(pr_number) => pr_number,
+        None => {
+            if status == 200 {
+                status = 400;
+            }
+
+            let error = page.error.as_deref().unwrap_or("No PR number given.");
+            return match format {
+                "json" => json_response(status, json!({ "error": error })),
+                _ => plain_response(status, format!("{}\n", error)),
+            };
+        }
+    };
+
+    let tree = page.tree.as_ref();
+
+    // Without a tree, the other formats can only show the PR as
+    // closed, so if that's not why there isn't one, say what went
+    // wrong instead.
+    if format != "json" && tree.is_none() && !page.closed {
+        if status == 200 {
+            status = 500;
+        }
+
+        let error = page.error.as_deref().unwrap_or("Couldn't track this PR.");
+        return plain_response(status, format!("{}\n", error));
+    }
+
+    match format {
+        "json" => json_response(
+            status,
+            json!({
+                "number": pr_number,
+                "url": page.pr_url,
+                "title": page.pr_title,
+                "author": page.pr_author,
+                "author_url": page.pr_author_url,
+                "labels": page.labels,
+                "milestone": page.milestone,
+                "merged_by": page.merged_by,
+                "merged_at": page.merged_at,
+                "closes": page.closes.iter().map(|issue| issue.number).collect::<Vec<_>>(),
+                "backports": page.backports,
+                "review": page.review,
+                "closed": page.closed,
+                "tree": tree,
+                "error": page.error,
+            }),
+        ),
+
+        "dot" => plain_response(
+            status,
+            export::dot(pr_number, page.merged_at.as_deref(), tree),
+        ),
+        "mermaid" => plain_response(status, export::mermaid(page.merged_at.as_deref(), tree)),
+
+        _ => plain_response(
+            status,
+            export::text(
+                pr_number,
+                page.pr_title.as_deref(),
+                page.error.as_deref(),
+                tree,
+                colour,
+            ),
+        ),
+    }
+}
+
 async fn handle_request<S>(request: Request<S>) -> http_types::Result<Response> {
     let mut status = 200;
     let mut page = PageTemplate {
+        watch_url: route("watch"),
         source_url: CONFIG.source_url.clone(),
         ..Default::default()
     };
 
-    let pr_number = request.query::<Query>()?.pr;
+    let query = request.query::<Query>()?;
 
-    track_pr(pr_number, &mut status, &mut page).await;
+    let forge = forge();
+    let nixpkgs = Nixpkgs::new(&CONFIG.path, &CONFIG.remote);
+
+    let negotiated = query.format.is_none() && query.pr.is_some();
+    let format = match query.format.as_deref() {
+        Some(format) => format,
+        None if negotiated => negotiate(
+            request.header("Accept").map(|h| h.as_str()),
+            request.header("User-Agent").map(|h| h.as_str()),
+        ),
+        None => "html",
+    };
+
+    let channels = CONFIG
+        .channels_url
+        .as_deref()
+        .map(|url| Channels::new(url, &CONFIG.user_agent));
 
-    Ok(Response::builder(status)
+    let heads = heads::seen().await;
+    let context = Context {
+        forge: &*forge,
+        nixpkgs: &nixpkgs,
+        channels: channels.as_ref(),
+        hydra_url: CONFIG.hydra_url.as_deref(),
+        releases: &RELEASES,
+        heads: &heads,
+    };
+
+    track_pr(context, query.pr, &mut status, &mut page).await;
+
+    if format != "html" {
+        let response = export_tree(format, status, &page, query.color.is_some());
+        return Ok(if negotiated { vary(response) } else { response });
+    }
+
+    // Link previews need an absolute URL, and the Host header can't
+    // be trusted to make one.
+    if let (Some(public_url), Some(pr_number)) = (&CONFIG.public_url, &page.pr_number) {
+        page.og_image = Some(format!(
+            "{}{}?pr={}",
+            public_url.trim_end_matches('/'),
+            route("og.png"),
+            pr_number
+        ));
+    }
+
+    search_prs(&*forge, &nixpkgs, query.q, &mut status, &mut page).await;
+    track_path(
+        &nixpkgs,
+        request.url(),
+        query.path,
+        query.attr,
+        query.page,
+        &mut status,
+        &mut page,
+    )
+    .await;
+
+    let response = Response::builder(status)
         .content_type(mime::HTML)
         .body(page.render()?)
-        .build())
+        .build();
+
+    Ok(if negotiated { vary(response) } else { response })
 }
 
 #[async_std::main]
@@ -179,12 +972,25 @@ async fn main() {
 
     // Make sure arguments are parsed before starting server.
     let _ = *CONFIG;
-    let _ = *GITHUB_TOKEN;
+    let _ = *API_URL;
+    let _ = *TOKEN;
+    let _ = *COOKIE_KEY;
+
+    handle_error(branches::validate(), 70, "next branch table");
+
+    async_std::task::spawn(heads::watch(Nixpkgs::new(&CONFIG.path, &CONFIG.remote)));
 
     let mut server = tide::new();
     let mut root = server.at(&CONFIG.mount);
 
     root.at("/").get(handle_request);
+    root.at("branches").get(overview::handle_branches);
+    root.at("compare").get(compare::handle_compare);
+    root.at("og.png").get(og_image::handle_og_png);
+    root.at("og.svg").get(og_image::handle_og_svg);
+    root.at("watch")
+        .get(watch::handle_watch)
+        .post(watch::handle_watch_update);
 
     let fd_count = handle_error(listen_fds(true), 71, "sd_listen_fds");
 
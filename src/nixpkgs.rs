@@ -16,6 +16,9 @@ use async_std::process::{Command, Stdio};
 pub enum Error {
     Io(io::Error),
     ExitFailure(ExitStatus),
+
+    /// Git said something we didn't understand.
+    Output(String),
 }
 
 impl Display for Error {
@@ -27,6 +30,7 @@ impl Display for Error {
                 Some(code) => write!(f, "git exited {}", code),
                 None => write!(f, "git killed by signal {}", e.signal().unwrap()),
             },
+            Output(output) => write!(f, "unexpected output from git: {:?}", output),
         }
     }
 }
@@ -35,6 +39,27 @@ impl std::error::Error for Error {}
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+#[derive(Debug)]
+pub struct LogEntry {
+    pub commit: String,
+
+    /// The branch the commit was found on.
+    pub branch: String,
+
+    pub subject: String,
+    pub body: String,
+}
+
+/// Where a branch's head is.
+#[derive(Debug)]
+pub struct Head {
+    pub branch: String,
+    pub commit: String,
+
+    /// The time the head commit was committed, in ISO 8601 format.
+    pub committed: String,
+}
+
 fn check_status(status: ExitStatus) -> Result<()> {
     if status.success() {
         Ok(())
@@ -76,6 +101,483 @@ impl<'a> Nixpkgs<'a> {
         Ok(output.stdout)
     }
 
+    /// The name of the remote-tracking ref for `branch`.
+    pub fn branch_ref(&self, branch: &str) -> OsString {
+        let mut r = OsString::from("refs/remotes/");
+        r.push(&self.remote_name);
+        r.push("/");
+        r.push(branch);
+        r
+    }
+
+    /// The paths of the files changed by `commit`.  For a merge
+    /// commit, that means the files changed relative to its first
+    /// parent, i.e. by the change that was merged.
+    pub async fn changed_files(&self, commit: &str) -> Result<Vec<String>> {
+        let output = self
+            .git_command("diff-tree")
+            .args(&["-r", "-z", "--name-only", "--no-commit-id"])
+            .arg(format!("{}^", commit))
+            .arg(commit)
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(output
+            .stdout
+            .split(|byte| *byte == b'\0')
+            .filter(|b| !b.is_empty())
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .collect())
+    }
+
+    /// The contents of the file at `path` in `rev`, or `None` if there
+    /// is no such file.
+    pub async fn file_at(&self, rev: impl AsRef<OsStr>, path: &str) -> Result<Option<Vec<u8>>> {
+        let mut object = rev.as_ref().to_owned();
+        object.push(":");
+        object.push(path);
+
+        let output = self
+            .git_command("cat-file")
+            .arg("blob")
+            .arg(object)
+            .stderr(Stdio::null())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(Some(output.stdout))
+    }
+
+    fn remote_prefix(&self) -> PathBuf {
+        let mut prefix = PathBuf::from("refs/remotes/");
+        prefix.push(&self.remote_name);
+        prefix
+    }
+
+    /// The name of the branch `refname` refers to, if it's one of the
+    /// remote's branches.
+    fn branch_name(&self, refname: &[u8]) -> Option<String> {
+        let branch = Path::new(OsStr::from_bytes(refname))
+            .strip_prefix(self.remote_prefix())
+            .ok()?;
+        Some(branch.to_str()?.to_string())
+    }
+
+    /// The names of all of the remote's branches.
+    pub async fn branches(&self) -> Result<Vec<String>> {
+        let output = self
+            .git_command("for-each-ref")
+            .arg("--format=%(refname)")
+            .arg(self.remote_prefix())
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(output
+            .stdout
+            .split(|byte| *byte == b'\n')
+            .filter_map(|r| self.branch_name(r))
+            .collect())
+    }
+
+    /// The heads of all of the remote's branches.
+    pub async fn heads(&self) -> Result<Vec<Head>> {
+        let output = self
+            .git_command("for-each-ref")
+            .arg("--format=%(refname)%00%(objectname)%00%(committerdate:iso-strict)")
+            .arg(self.remote_prefix())
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(output
+            .stdout
+            .split(|byte| *byte == b'\n')
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, |byte| *byte == b'\0');
+                let branch = self.branch_name(fields.next()?)?;
+                let commit = String::from_utf8_lossy(fields.next()?).into_owned();
+                let committed = String::from_utf8_lossy(fields.next()?).into_owned();
+
+                Some(Head {
+                    branch,
+                    commit,
+                    committed,
+                })
+            })
+            .collect())
+    }
+
+    /// The number of commits reachable from `include`, but not from
+    /// `exclude`.
+    pub async fn count_commits(&self, include: &str, exclude: &str) -> Result<usize> {
+        let output = self
+            .git_command("rev-list")
+            .arg("--count")
+            .arg(include)
+            .arg(format!("^{}", exclude))
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        let count = String::from_utf8_lossy(&output.stdout);
+        count
+            .trim()
+            .parse()
+            .map_err(|_| Error::Output(count.into_owned()))
+    }
+
+    /// Whether `ancestor` is an ancestor of `commit`, or the same
+    /// commit.
+    pub async fn is_ancestor(&self, ancestor: &str, commit: &str) -> Result<bool> {
+        let status = self
+            .git_command("merge-base")
+            .arg("--is-ancestor")
+            .arg(ancestor)
+            .arg(commit)
+            .stderr(Stdio::inherit())
+            .status()
+            .await
+            .map_err(Error::Io)?;
+
+        // git merge-base exits 1 if it isn't an ancestor, and
+        // something else if it couldn't tell.
+        match status.code() {
+            Some(1) => Ok(false),
+            _ => check_status(status).map(|()| true),
+        }
+    }
+
+    /// Whether the object `oid` is in the local checkout.
+    pub async fn has_object(&self, oid: &str) -> Result<bool> {
+        let status = self
+            .git_command("cat-file")
+            .arg("-e")
+            .arg(oid)
+            .stderr(Stdio::inherit())
+            .status()
+            .await
+            .map_err(Error::Io)?;
+
+        // git cat-file exits 1 if the object is missing, and
+        // something else if `oid` isn't an object name at all.
+        match status.code() {
+            Some(1) => Ok(false),
+            _ => check_status(status).map(|()| true),
+        }
+    }
+
+    /// The parents of `commit`, first parent first.
+    pub async fn parents(&self, commit: &str) -> Result<Vec<String>> {
+        let output = self
+            .git_command("rev-list")
+            .args(&["--parents", "--max-count=1"])
+            .arg(commit)
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .skip(1)
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// The commits and subjects of the last `count` commits on the
+    /// first-parent history of `commit`, newest first.
+    pub async fn first_parent_subjects(
+        &self,
+        commit: &str,
+        count: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let output = self
+            .git_command("log")
+            .args(&["-z", "--first-parent", "--format=%H%x1f%s"])
+            .arg(format!("--max-count={}", count))
+            .arg(commit)
+            .arg("--")
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(output
+            .stdout
+            .split(|byte| *byte == b'\0')
+            .filter_map(|record| {
+                let mut fields = record.splitn(2, |byte| *byte == 0x1f);
+                let commit = String::from_utf8_lossy(fields.next()?).into_owned();
+                let subject = String::from_utf8_lossy(fields.next()?).into_owned();
+                Some((commit, subject))
+            })
+            .collect())
+    }
+
+    /// The commits on `branches`, made after `since`, whose messages
+    /// say they revert any of `commits`, in the way `git revert`
+    /// writes them.
+    pub async fn reverts(
+        &self,
+        branches: &[&str],
+        since: &str,
+        commits: &[String],
+    ) -> Result<Vec<String>> {
+        let output = self
+            .git_command("rev-list")
+            .arg("--fixed-strings")
+            .args(
+                commits
+                    .iter()
+                    .map(|commit| format!("--grep=This reverts commit {}", commit)),
+            )
+            .args(branches.iter().map(|branch| self.branch_ref(branch)))
+            .arg(format!("^{}", since))
+            .arg("--")
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// The commits on the first-parent history of `branches` that
+    /// changed `path`, newest first, apart from those with a line in
+    /// their message matching the POSIX extended regular expression
+    /// `exclude`.  For a branch that changes are merged into, that
+    /// means the merges of the changes, rather than the commits that
+    /// made them.
+    pub async fn first_parent_log(
+        &self,
+        branches: &[String],
+        path: &str,
+        exclude: &str,
+        skip: usize,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>> {
+        // Excluding commits here, rather than afterwards, keeps the
+        // pages the same size.
+        let mut args = vec![
+            OsString::from("--first-parent"),
+            "--extended-regexp".into(),
+            "--invert-grep".into(),
+            format!("--grep={}", exclude).into(),
+            format!("--skip={}", skip).into(),
+            format!("--max-count={}", limit).into(),
+        ];
+        args.extend(branches.iter().map(|branch| self.branch_ref(branch)));
+        args.push("--".into());
+        args.push(path.into());
+
+        self.log(args).await
+    }
+
+    /// The commits on the first-parent history of `branch` whose
+    /// messages contain `text`, newest first.
+    pub async fn first_parent_grep(&self, branch: &str, text: &str) -> Result<Vec<LogEntry>> {
+        self.log(vec![
+            "--first-parent".into(),
+            "--fixed-strings".into(),
+            format!("--grep={}", text).into(),
+            self.branch_ref(branch),
+            "--".into(),
+        ])
+        .await
+    }
+
+    /// The commit on the first-parent history of `branch` that merged
+    /// `commit` into it, if there is one.
+    pub async fn merge_of(&self, branch: &str, commit: &str) -> Result<Option<String>> {
+        // The heads of PRs that were never merged, or were squashed,
+        // needn't have been fetched, and rev-list fails on them.
+        if !self.has_object(commit).await? {
+            return Ok(None);
+        }
+
+        // Only the commits since `commit` need to be looked at, and
+        // the merge is the oldest of those that has it as a parent.
+        let output = self
+            .git_command("rev-list")
+            .args(&["--first-parent", "--parents", "--reverse"])
+            .arg(self.branch_ref(branch))
+            .arg(format!("^{}", commit))
+            .arg("--")
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| {
+                let mut commits = line.split_whitespace();
+                let merge = commits.next()?;
+                match commits.skip(1).any(|parent| parent == commit) {
+                    true => Some(merge.to_string()),
+                    false => None,
+                }
+            }))
+    }
+
+    /// The oldest commit on the first-parent history of `branch` that
+    /// is descended from `commit`.  Unless `commit` is on the
+    /// first-parent history itself, that's the merge that brought it
+    /// into the branch.
+    pub async fn carrying_merge(&self, branch: &str, commit: &str) -> Result<Option<LogEntry>> {
+        // rev-list applies limits before reversing, so the whole list
+        // has to be generated, but only the oldest needs logging.
+        let output = self
+            .git_command("rev-list")
+            .args(&["--first-parent", "--ancestry-path", "--reverse"])
+            .arg(self.branch_ref(branch))
+            .arg(format!("^{}", commit))
+            .arg("--")
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        let oldest = match String::from_utf8_lossy(&output.stdout).lines().next() {
+            Some(oldest) => oldest.to_string(),
+            None => return Ok(None),
+        };
+
+        let mut entries = self
+            .log(vec!["-1".into(), oldest.into(), "--".into()])
+            .await?;
+        Ok(entries.pop().map(|entry| LogEntry {
+            branch: branch.to_string(),
+            ..entry
+        }))
+    }
+
+    /// The first commit on the first-parent history of `branch` to
+    /// contain `commit`, which the branch must contain.
+    pub async fn first_containing(&self, branch: &str, commit: &str) -> Result<String> {
+        let entry = match self.carrying_merge(branch, commit).await? {
+            Some(entry) => entry,
+            // Nothing's been added to the branch since.
+            None => return Ok(commit.to_string()),
+        };
+
+        // If the oldest descendant of `commit` is its child, `commit`
+        // is on the first-parent history itself.
+        let parents = self.parents(&entry.commit).await?;
+        if parents.first().map(String::as_str) == Some(commit) {
+            return Ok(commit.to_string());
+        }
+
+        Ok(entry.commit)
+    }
+
+    /// The commits on `from` that aren't on `to`, newest first.
+    pub async fn log_range(&self, from: &str, to: &str, limit: usize) -> Result<Vec<LogEntry>> {
+        let mut exclude = OsString::from("^");
+        exclude.push(self.branch_ref(to));
+
+        self.log(vec![
+            format!("--max-count={}", limit).into(),
+            self.branch_ref(from),
+            exclude,
+            "--".into(),
+        ])
+        .await
+    }
+
+    /// The commits on the first-parent history of `from` that aren't
+    /// on `to`, newest first.  For a branch that changes are merged
+    /// into, those are the merges, and the commits pushed or rebased
+    /// directly onto it.
+    pub async fn first_parent_range(
+        &self,
+        from: &str,
+        to: &str,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>> {
+        let mut exclude = OsString::from("^");
+        exclude.push(self.branch_ref(to));
+
+        self.log(vec![
+            "--first-parent".into(),
+            format!("--max-count={}", limit).into(),
+            self.branch_ref(from),
+            exclude,
+            "--".into(),
+        ])
+        .await
+    }
+
+    /// Run `git log` with `args`, and parse its output.
+    async fn log(&self, args: Vec<OsString>) -> Result<Vec<LogEntry>> {
+        let output = self
+            .git_command("log")
+            .args(&["-z", "--source", "--format=%H%x1f%S%x1f%s%x1f%b"])
+            .args(args)
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .map_err(Error::Io)?;
+
+        check_status(output.status)?;
+
+        Ok(output
+            .stdout
+            .split(|byte| *byte == b'\0')
+            .filter_map(|record| {
+                let mut fields = record.splitn(4, |byte| *byte == 0x1f);
+                let commit = String::from_utf8_lossy(fields.next()?).into_owned();
+                let source = fields.next()?;
+                let subject = String::from_utf8_lossy(fields.next()?).into_owned();
+                let body = String::from_utf8_lossy(fields.next()?).into_owned();
+
+                // Commits asked for by ID are found from themselves.
+                let branch = self
+                    .branch_name(source)
+                    .unwrap_or_else(|| String::from_utf8_lossy(source).into_owned());
+
+                Some(LogEntry {
+                    commit,
+                    branch,
+                    subject,
+                    body,
+                })
+            })
+            .collect())
+    }
+
     async fn git_fetch_nixpkgs(&self) -> Result<()> {
         // TODO: add refspecs
         self.git_command("fetch")
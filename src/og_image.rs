@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Drawing a PR's tree as an image, for link previews.  Most sites
+//! that show previews don't do much with `og:description`, so a line
+//! of emoji isn't very readable there.
+
+use std::error::Error;
+use std::time::Duration;
+
+use askama::Template;
+use http_types::{mime, StatusCode};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tide::{Request, Response};
+
+use crate::cache::Cache;
+use crate::forge::{self, PullRequestStatus};
+use crate::nixpkgs::Nixpkgs;
+use crate::tree::{State, Tree};
+use crate::{CONFIG, RELEASES};
+
+/// The size Open Graph recommends for images.
+const WIDTH: u32 = 1200;
+const MIN_HEIGHT: u32 = 630;
+
+const LEFT: u32 = 60;
+const TOP: u32 = 130;
+const ROW_HEIGHT: u32 = 56;
+const INDENT: u32 = 48;
+
+/// Titles longer than this wouldn't fit across the image.
+const MAX_TITLE_CHARS: usize = 55;
+
+/// Rendered images, keyed by PR number and the SVG they were drawn
+/// from, so an image is only drawn again when the PR's state changes.
+static PNGS: Lazy<Cache<(i64, String), Vec<u8>>> =
+    Lazy::new(|| Cache::new(Duration::from_secs(60 * 60), 256));
+
+static OPTIONS: Lazy<usvg::Options> = Lazy::new(|| {
+    let mut options = usvg::Options::default();
+    options.fontdb.load_system_fonts();
+    options
+});
+
+#[derive(Debug)]
+struct Node {
+    x: u32,
+    y: u32,
+    fill: &'static str,
+    label: String,
+}
+
+/// A line from a node down to one of its children.
+#[derive(Debug)]
+struct Edge {
+    x: u32,
+    y1: u32,
+    x2: u32,
+    y2: u32,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "og.svg", escape = "html")]
+pub struct OgImage {
+    pr_number: i64,
+    title: String,
+    height: u32,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    pr: Option<String>,
+}
+
+fn layout(tree: &Tree, depth: u32, nodes: &mut Vec<Node>, edges: &mut Vec<Edge>) {
+    let x = LEFT + depth * INDENT;
+    let y = TOP + nodes.len() as u32 * ROW_HEIGHT;
+
+    nodes.push(Node {
+        x,
+        y,
+        fill: tree.state().colour(),
+        label: tree.branch_name.clone(),
+    });
+
+    for child in tree.children.iter() {
+        edges.push(Edge {
+            x,
+            y1: y,
+            x2: x + INDENT,
+            y2: TOP + nodes.len() as u32 * ROW_HEIGHT,
+        });
+        layout(child, depth + 1, nodes, edges);
+    }
+}
+
+impl OgImage {
+    /// An image of `tree`, or of a closed PR if there's no tree.
+    pub fn new(pr_number: i64, title: &str, tree: Option<&Tree>) -> Self {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+
+        match tree {
+            Some(tree) => layout(tree, 0, &mut nodes, &mut edges),
+            None => nodes.push(Node {
+                x: LEFT,
+                y: TOP,
+                fill: State::Rejected.colour(),
+                label: "closed".to_string(),
+            }),
+        }
+
+        let mut title = format!("#{} {}", pr_number, title);
+        if title.chars().count() > MAX_TITLE_CHARS {
+            title = title.chars().take(MAX_TITLE_CHARS - 1).collect();
+            title.push('…');
+        }
+
+        Self {
+            pr_number,
+            title,
+            height: MIN_HEIGHT.max(TOP + nodes.len() as u32 * ROW_HEIGHT),
+            nodes,
+            edges,
+        }
+    }
+
+    fn png(svg: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let tree = usvg::Tree::from_str(svg, &OPTIONS.to_ref())?;
+
+        let size = tree.svg_node().size.to_screen_size();
+        let mut pixmap =
+            tiny_skia::Pixmap::new(size.width(), size.height()).ok_or("empty image")?;
+        resvg::render(
+            &tree,
+            usvg::FitTo::Original,
+            tiny_skia::Transform::default(),
+            pixmap.as_mut(),
+        )
+        .ok_or("rendering failed")?;
+
+        Ok(pixmap.encode_png()?)
+    }
+
+    /// The image as a PNG.  Drawing it takes long enough that it's
+    /// done on a thread of its own, rather than holding up other
+    /// requests.
+    pub async fn render_png(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let key = (self.pr_number, self.render()?);
+        if let Some(png) = PNGS.get(&key) {
+            return Ok(png);
+        }
+
+        let svg = key.1.clone();
+        let png = async_std::task::spawn_blocking(move || Self::png(&svg)).await?;
+        PNGS.insert(key, png.clone());
+        Ok(png)
+    }
+}
+
+async fn og_image(pr: Option<String>) -> http_types::Result<OgImage> {
+    let pr_number: i64 = pr
+        .as_deref()
+        .and_then(|pr| pr.parse().ok())
+        .ok_or_else(|| http_types::Error::from_str(StatusCode::BadRequest, "Invalid PR number"))?;
+
+    let pr_info = match crate::forge().pr_info(pr_number).await {
+        Ok(info) => info,
+        Err(forge::Error::NotFound) => {
+            return Err(http_types::Error::from_str(
+                StatusCode::NotFound,
+                "No such PR",
+            ))
+        }
+        Err(e) => {
+            return Err(http_types::Error::from_str(
+                StatusCode::InternalServerError,
+                e,
+            ))
+        }
+    };
+
+    if matches!(pr_info.status, PullRequestStatus::Closed) {
+        return Ok(OgImage::new(pr_number, &pr_info.title, None));
+    }
+
+    let nixpkgs = Nixpkgs::new(&CONFIG.path, &CONFIG.remote);
+    let tree = Tree::make(pr_info.branch, &pr_info.status, &RELEASES, &nixpkgs)
+        .await
+        .map_err(|e| http_types::Error::from_str(StatusCode::InternalServerError, e))?;
+
+    Ok(OgImage::new(pr_number, &pr_info.title, Some(&tree)))
+}
+
+pub async fn handle_og_svg<S>(request: Request<S>) -> http_types::Result<Response> {
+    let query = request.query::<Query>()?;
+    let image = og_image(query.pr).await?;
+
+    Ok(Response::builder(200)
+        .content_type(mime::SVG)
+        .body(image.render()?)
+        .build())
+}
+
+pub async fn handle_og_png<S>(request: Request<S>) -> http_types::Result<Response> {
+    let query = request.query::<Query>()?;
+    let image = og_image(query.pr).await?;
+    let png = image
+        .render_png()
+        .await
+        .map_err(|e| http_types::Error::from_str(StatusCode::InternalServerError, e))?;
+
+    Ok(Response::builder(200)
+        .content_type(mime::PNG)
+        .body(png)
+        .build())
+}
@@ -3,13 +3,14 @@
 
 use askama::Template;
 
+use crate::tree::State;
 use crate::Tree;
 
-#[derive(Debug, Template)]
+#[derive(Clone, Debug, Template)]
 #[template(path = "ogmeta.html")]
 pub struct Ogmeta {
     branch_name: String,
-    accepted: Option<bool>,
+    state: State,
     children: Vec<Ogmeta>,
 }
 
@@ -17,8 +18,12 @@ impl Ogmeta {
     pub fn from_tree(tree: &Tree) -> Ogmeta {
         Ogmeta {
             branch_name: tree.branch_name.clone(),
-            accepted: tree.accepted.clone(),
-            children: tree.children.iter().map(|c| Ogmeta::from_tree(&c)).collect(),
+            state: tree.state(),
+            children: tree
+                .children
+                .iter()
+                .map(|c| Ogmeta::from_tree(&c))
+                .collect(),
         }
     }
 }
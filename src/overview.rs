@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! An overview of the branch graph, with where each branch is, and
+//! how far behind the branch before it each one is.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use askama::Template;
+use futures_util::future::join_all;
+use http_types::mime;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tide::{Request, Response};
+
+use crate::branches::{next_branches, Graph};
+use crate::cache::Cache;
+use crate::heads::{self, Heads};
+use crate::nixpkgs::{self, Head, Nixpkgs};
+use crate::releases::Releases;
+use crate::{CONFIG, RELEASES};
+
+/// Commit counts, keyed by the commits they're between, so they never
+/// go out of date, and are only forgotten to save memory.
+static BEHIND: Lazy<Cache<(String, String), usize>> =
+    Lazy::new(|| Cache::new(Duration::from_secs(24 * 60 * 60), 4096));
+
+#[derive(Debug, Serialize, Template)]
+#[template(path = "branch.html")]
+pub struct BranchNode {
+    pub name: String,
+    pub head: String,
+
+    /// When the head commit was committed.
+    pub committed: String,
+
+    /// When pr-tracker last saw the branch move, if it has since it
+    /// started.
+    pub last_moved: Option<String>,
+
+    /// When pr-tracker last saw the branch be rewritten, rather than
+    /// fast-forwarded, if it has since it started.
+    pub last_rewritten: Option<String>,
+
+    /// The number of commits on the branch before this one in the
+    /// graph that haven't reached this one yet.
+    pub behind: Option<usize>,
+
+    /// Whether the branch also comes after another branch earlier in
+    /// the graph, where its children are shown instead.
+    pub shared: bool,
+
+    pub children: Vec<BranchNode>,
+}
+
+impl BranchNode {
+    fn short_head(&self) -> &str {
+        self.head.get(..12).unwrap_or(&self.head)
+    }
+}
+
+#[derive(Debug, Default, Template)]
+#[template(path = "branches.html")]
+struct BranchesTemplate {
+    mount: String,
+    error: Option<String>,
+    roots: Vec<BranchNode>,
+    source_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    format: Option<String>,
+}
+
+/// The node for `head`, and the branches after it in `graph`.
+/// `found_branches` is the branches already in the graph, whose
+/// children aren't shown again.
+fn node(
+    graph: &Graph,
+    head: &Head,
+    behind: Option<usize>,
+    heads: &BTreeMap<&str, &Head>,
+    edges: &BTreeMap<(&str, &str), usize>,
+    seen: &Heads,
+    found_branches: &mut BTreeSet<String>,
+) -> BranchNode {
+    let shared = !found_branches.insert(head.branch.clone());
+
+    let mut children = Vec::new();
+    if !shared {
+        for next in graph.nexts(&head.branch) {
+            let behind = match edges.get(&(head.branch.as_str(), next.as_str())) {
+                Some(behind) => *behind,
+                None => continue,
+            };
+            if let Some(next_head) = heads.get(next.as_str()) {
+                let child = node(
+                    graph,
+                    next_head,
+                    Some(behind),
+                    heads,
+                    edges,
+                    seen,
+                    found_branches,
+                );
+                children.push(child);
+            }
+        }
+    }
+
+    BranchNode {
+        name: head.branch.clone(),
+        head: head.commit.clone(),
+        committed: head.committed.clone(),
+        last_moved: seen
+            .last_moved(&head.branch)
+            .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+        last_rewritten: seen
+            .last_rewritten(&head.branch)
+            .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+        behind,
+        shared,
+        children,
+    }
+}
+
+/// The graph of the branches in the local checkout, starting from
+/// the branches that nothing else goes into, with when each was
+/// `seen` to move.  The branches of releases that are long gone are
+/// left out.
+pub async fn branch_graph(
+    nixpkgs: &Nixpkgs<'_>,
+    seen: &Heads,
+    releases: &Releases,
+) -> Result<Vec<BranchNode>, nixpkgs::Error> {
+    let all_heads = nixpkgs.heads().await?;
+
+    let heads: BTreeMap<_, _> = all_heads
+        .iter()
+        .filter(|head| !releases.is_long_gone(&head.branch))
+        .map(|head| (head.branch.as_str(), head))
+        .collect();
+
+    let mut pairs = Vec::new();
+    for head in heads.values().copied() {
+        for next in next_branches(&head.branch) {
+            if let Some((next, next_head)) = heads.get_key_value(next.as_ref()) {
+                pairs.push((head, *next, *next_head));
+            }
+        }
+    }
+
+    // Each count is a separate git process, so run them all at once.
+    let counts = join_all(pairs.iter().map(|(head, _, next_head)| async move {
+        let key = (head.commit.clone(), next_head.commit.clone());
+        if let Some(behind) = BEHIND.get(&key) {
+            return Ok(behind);
+        }
+
+        let behind = nixpkgs
+            .count_commits(&head.commit, &next_head.commit)
+            .await?;
+        BEHIND.insert(key, behind);
+        Ok::<_, nixpkgs::Error>(behind)
+    }))
+    .await;
+
+    let mut edges = BTreeMap::new();
+    for ((head, next, _), behind) in pairs.iter().zip(counts) {
+        edges.insert((head.branch.as_str(), *next), behind?);
+    }
+
+    let targets: BTreeSet<_> = edges.keys().map(|(_, to)| *to).collect();
+
+    let mut roots = Vec::new();
+    for branch in edges.keys().map(|(from, _)| *from).collect::<BTreeSet<_>>() {
+        if targets.contains(branch) {
+            continue;
+        }
+
+        let graph = match Graph::supported(branch, releases) {
+            Ok(graph) => graph,
+            Err(e) => {
+                eprintln!("pr-tracker: branch graph: {}", e);
+                continue;
+            }
+        };
+
+        let head = heads[branch];
+        let mut found_branches = BTreeSet::new();
+        roots.push(node(
+            &graph,
+            head,
+            None,
+            &heads,
+            &edges,
+            seen,
+            &mut found_branches,
+        ));
+    }
+
+    Ok(roots)
+}
+
+pub async fn handle_branches<S>(request: Request<S>) -> http_types::Result<Response> {
+    let query = request.query::<Query>()?;
+    let nixpkgs = Nixpkgs::new(&CONFIG.path, &CONFIG.remote);
+
+    let mut status = 200;
+    let mut page = BranchesTemplate {
+        mount: crate::route(""),
+        source_url: CONFIG.source_url.clone(),
+        ..Default::default()
+    };
+
+    match branch_graph(&nixpkgs, &heads::seen().await, &RELEASES).await {
+        Ok(roots) => page.roots = roots,
+        Err(e) => {
+            status = 500;
+            page.error = Some(e.to_string());
+        }
+    }
+
+    if query.format.as_deref() == Some("json") {
+        let body = match page.error {
+            Some(error) => serde_json::json!({ "error": error }),
+            None => serde_json::to_value(&page.roots)?,
+        };
+
+        return Ok(Response::builder(status)
+            .content_type(mime::JSON)
+            .body(body)
+            .build());
+    }
+
+    Ok(Response::builder(status)
+        .content_type(mime::HTML)
+        .body(page.render()?)
+        .build())
+}
@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Working out which packages a change touches, and what version of
+//! them each branch has.
+//!
+//! Evaluating Nixpkgs would be far too slow to do for every request,
+//! so this is all guesswork based on the layout of the tree: a
+//! package's attribute name is assumed to be the name of the
+//! directory its expression is in, and its version is whatever
+//! `version = "...";` that expression contains.
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::nixpkgs::{self, Nixpkgs};
+
+/// A mass rebuild can touch thousands of packages, and nobody wants
+/// to see versions for all of them.
+const MAX_PACKAGES: usize = 10;
+
+const ALL_PACKAGES: &str = "pkgs/top-level/all-packages.nix";
+
+static VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?m)^\s*version\s*=\s*"([^"$]+)"\s*;"#).unwrap());
+
+static ATTR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\A[A-Za-z_][A-Za-z0-9_'-]*\z").unwrap());
+
+#[derive(Debug, PartialEq)]
+pub struct Package {
+    pub attr: String,
+
+    /// The file expected to contain the package's version.
+    pub file: String,
+}
+
+/// A package, with its version on some branch.
+#[derive(Clone, Debug, Serialize)]
+pub struct PackageVersion {
+    pub attr: String,
+
+    /// The version before the change was made.
+    pub before: Option<String>,
+
+    pub version: Option<String>,
+}
+
+impl Display for PackageVersion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.attr)?;
+        match (&self.before, &self.version) {
+            (Some(before), Some(version)) if before != version => {
+                write!(f, "{} → {}", before, version)
+            }
+            (_, Some(version)) => write!(f, "{}", version),
+            (_, None) => write!(f, "absent"),
+        }
+    }
+}
+
+/// Returns the directory the package is defined in, along with the
+/// package.
+fn package_for_path(path: &str) -> Option<(String, Package)> {
+    let components: Vec<_> = path.split('/').collect();
+    let dir = Path::new(path).parent()?.to_str()?.to_string();
+
+    match components.as_slice() {
+        // pkgs/by-name/he/hello/package.nix
+        ["pkgs", "by-name", shard, attr, _, ..] => {
+            let dir = format!("pkgs/by-name/{}/{}", shard, attr);
+            Some((
+                dir.clone(),
+                Package {
+                    attr: attr.to_string(),
+                    file: format!("{}/package.nix", dir),
+                },
+            ))
+        }
+
+        ["pkgs", "by-name", ..] | ["pkgs", "top-level", ..] => None,
+
+        // pkgs/applications/networking/browsers/firefox/packages.nix
+        ["pkgs", .., attr, file] => {
+            // If the changed file is a Nix expression, it's the most
+            // likely place to find the version that was changed.
+            // Otherwise, it's probably a patch or something, and
+            // the version will be in default.nix.
+            let file = if file.ends_with(".nix") {
+                path.to_string()
+            } else {
+                format!("{}/default.nix", dir)
+            };
+
+            Some((
+                dir,
+                Package {
+                    attr: attr.to_string(),
+                    file,
+                },
+            ))
+        }
+
+        _ => None,
+    }
+}
+
+/// Guess the packages affected by changes to `paths`.
+pub fn packages_for_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<Package> {
+    let mut seen = BTreeSet::new();
+
+    paths
+        .into_iter()
+        .filter_map(package_for_path)
+        .filter(|(dir, _)| seen.insert(dir.clone()))
+        .map(|(_, package)| package)
+        .take(MAX_PACKAGES)
+        .collect()
+}
+
+fn parse_version(expression: &[u8]) -> Option<String> {
+    let expression = String::from_utf8_lossy(expression);
+    Some(VERSION.captures(&expression)?[1].to_string())
+}
+
+pub async fn version_at(
+    nixpkgs: &Nixpkgs<'_>,
+    rev: impl AsRef<OsStr>,
+    package: &Package,
+) -> Result<Option<String>, nixpkgs::Error> {
+    Ok(nixpkgs
+        .file_at(rev, &package.file)
+        .await?
+        .as_deref()
+        .and_then(parse_version))
+}
+
+/// The packages touched by `commit`, with their versions from before
+/// it was made.
+pub async fn changed_packages(
+    nixpkgs: &Nixpkgs<'_>,
+    commit: &str,
+) -> Result<Vec<(Package, Option<String>)>, nixpkgs::Error> {
+    let files = nixpkgs.changed_files(commit).await?;
+    let parent = format!("{}^", commit);
+
+    let mut packages = Vec::new();
+    for package in packages_for_paths(files.iter().map(String::as_str)) {
+        let before = version_at(nixpkgs, &parent, &package).await?;
+        packages.push((package, before));
+    }
+
+    Ok(packages)
+}
+
+/// Find the path `attr` is called from in all-packages.nix, relative
+/// to the root of the tree.
+fn call_package_path(all_packages: &str, attr: &str) -> Option<String> {
+    let call_package = Regex::new(&format!(
+        r"(?m)^\s*{}\s*=\s*callPackages?\s+\.\./([^\s;{{}}]+)",
+        regex::escape(attr)
+    ))
+    .unwrap();
+
+    let path = call_package.captures(all_packages)?[1]
+        .trim_end_matches('/')
+        .to_string();
+
+    // We want the directory, so that changes to patches and the like
+    // are included too.
+    let path = match path.strip_suffix(".nix") {
+        Some(_) => Path::new(&path).parent()?.to_str()?.to_string(),
+        None => path,
+    };
+
+    Some(format!("pkgs/{}", path))
+}
+
+/// Guess the directory containing the expression for `attr` in `rev`.
+pub async fn path_for_attr(
+    nixpkgs: &Nixpkgs<'_>,
+    rev: impl AsRef<OsStr>,
+    attr: &str,
+) -> Result<Option<String>, nixpkgs::Error> {
+    if !ATTR.is_match(attr) {
+        return Ok(None);
+    }
+
+    let rev = rev.as_ref();
+
+    let shard = attr.chars().take(2).collect::<String>().to_lowercase();
+    let by_name = format!("pkgs/by-name/{}/{}", shard, attr);
+    let package = format!("{}/package.nix", by_name);
+    if nixpkgs.file_at(rev, &package).await?.is_some() {
+        return Ok(Some(by_name));
+    }
+
+    Ok(nixpkgs
+        .file_at(rev, ALL_PACKAGES)
+        .await?
+        .and_then(|all_packages| call_package_path(&String::from_utf8_lossy(&all_packages), attr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_name() {
+        let res = packages_for_paths(vec![
+            "pkgs/by-name/he/hello/package.nix",
+            "pkgs/by-name/he/hello/fix.patch",
+        ]);
+        assert_eq!(
+            res,
+            vec![Package {
+                attr: "hello".to_string(),
+                file: "pkgs/by-name/he/hello/package.nix".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn by_category() {
+        let res = packages_for_paths(vec![
+            "pkgs/applications/networking/browsers/firefox/packages.nix",
+            "pkgs/top-level/all-packages.nix",
+            "nixos/modules/programs/firefox.nix",
+        ]);
+        assert_eq!(
+            res,
+            vec![Package {
+                attr: "firefox".to_string(),
+                file: "pkgs/applications/networking/browsers/firefox/packages.nix".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn version() {
+        let expression = b"{ stdenv }:\n\nstdenv.mkDerivation rec {\n  pname = \"hello\";\n  version = \"2.12.1\";\n}\n";
+        assert_eq!(parse_version(expression), Some("2.12.1".to_string()));
+    }
+
+    #[test]
+    fn interpolated_version() {
+        let expression = b"  version = \"${major}.1\";\n";
+        assert_eq!(parse_version(expression), None);
+    }
+
+    #[test]
+    fn call_package() {
+        let all_packages = "  firefox = wrapFirefox firefox-unwrapped { };\n  firefox-unwrapped = callPackage ../applications/networking/browsers/firefox/packages.nix { };\n  hello = callPackage ../applications/misc/hello { };\n";
+        assert_eq!(
+            call_package_path(all_packages, "hello"),
+            Some("pkgs/applications/misc/hello".to_string())
+        );
+        assert_eq!(
+            call_package_path(all_packages, "firefox-unwrapped"),
+            Some("pkgs/applications/networking/browsers/firefox".to_string())
+        );
+        assert_eq!(call_package_path(all_packages, "firefox"), None);
+    }
+}
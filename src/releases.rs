@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! Which releases are still supported.  Nothing about a release
+//! branch says when it stops being supported, so when each release
+//! reaches its end of life has to be configured.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// How long after a release's end of life to stop showing it at all,
+/// rather than showing it as having reached its end of life.
+const LONG_GONE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// The release a branch belongs to, like 21.05 in release-21.05,
+/// staging-next-21.05 or nixos-21.05-small.
+static VERSION: Lazy<Regex> = Lazy::new(|| Regex::new(r"-(\d{2}\.\d{2})(?:-|\z)").unwrap());
+
+/// When a release reaches its end of life, written like
+/// "21.05=2021-12-31".
+#[derive(Debug)]
+pub struct EndOfLife {
+    pub version: String,
+    pub date: SystemTime,
+}
+
+impl FromStr for EndOfLife {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (version, date) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <release>=<date>: {}", s))?;
+
+        let date = humantime::parse_rfc3339(&format!("{}T00:00:00Z", date))
+            .map_err(|e| format!("{}: {}", date, e))?;
+
+        Ok(Self {
+            version: version.to_string(),
+            date,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Releases {
+    end_of_life: BTreeMap<String, SystemTime>,
+}
+
+impl Releases {
+    pub fn new(end_of_life: &[EndOfLife]) -> Self {
+        Self {
+            end_of_life: end_of_life
+                .iter()
+                .map(|eol| (eol.version.clone(), eol.date))
+                .collect(),
+        }
+    }
+
+    /// How long ago the release that `branch` belongs to reached its
+    /// end of life, if it has.
+    fn ended(&self, branch: &str) -> Option<Duration> {
+        let version = VERSION.captures(branch)?;
+        let date = self.end_of_life.get(&version[1])?;
+        SystemTime::now().duration_since(*date).ok()
+    }
+
+    pub fn is_end_of_life(&self, branch: &str) -> bool {
+        self.ended(branch).is_some()
+    }
+
+    /// Whether the release that `branch` belongs to reached its end of
+    /// life so long ago that there's no point showing it.
+    pub fn is_long_gone(&self, branch: &str) -> bool {
+        self.ended(branch).map_or(false, |ago| ago > LONG_GONE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn end_of_life() {
+        let releases = Releases::new(&["20.09=2021-06-30".parse().unwrap()]);
+        for branch in &["release-20.09", "staging-next-20.09", "nixos-20.09-small"] {
+            assert!(releases.is_end_of_life(branch), "{}", branch);
+            assert!(releases.is_long_gone(branch), "{}", branch);
+        }
+        assert!(!releases.is_end_of_life("release-21.05"));
+        assert!(!releases.is_end_of_life("master"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("20.09".parse::<EndOfLife>().is_err());
+        assert!("20.09=June".parse::<EndOfLife>().is_err());
+    }
+}
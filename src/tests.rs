@@ -0,0 +1,971 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! End-to-end tests of `track_pr`, the watch dashboard and branch
+//! comparison, against a synthetic Nixpkgs checkout and a local mock
+//! of the GitHub GraphQL API.
+
+mod fixture;
+mod mock_channels;
+mod mock_github;
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+use serde_json::{json, Value};
+
+use super::*;
+use fixture::Fixture;
+use mock_github::pull_request;
+use tree::State;
+
+const UNKNOWN_COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+
+struct Setup {
+    fixture: Fixture,
+    api_url: String,
+    repository: forge::Repository,
+    token: TokenProvider,
+}
+
+impl Setup {
+    async fn new() -> Self {
+        let fixture = Fixture::new();
+
+        let mut pull_requests = BTreeMap::new();
+        pull_requests.insert(1, pull_request("master", None, None, false));
+        pull_requests.insert(
+            2,
+            pull_request(
+                "staging",
+                Some("2021-05-01T12:00:00Z"),
+                Some(fixture.staging_merge.as_str()),
+                true,
+            ),
+        );
+        pull_requests.insert(3, pull_request("master", None, None, true));
+        // A PR merged without GitHub saying where, whose head was
+        // fetched, but never merged itself.
+        let mut squashed_pr = pull_request("master", Some("2015-01-01T12:00:00Z"), None, true);
+        squashed_pr["headRefOid"] = json!(fixture.unmerged_head);
+        pull_requests.insert(4, squashed_pr);
+        pull_requests.insert(
+            5,
+            pull_request(
+                "master",
+                Some("2021-05-01T12:00:00Z"),
+                Some(UNKNOWN_COMMIT),
+                true,
+            ),
+        );
+
+        // PRs merged before GitHub started telling us their merge
+        // commits, that can be found by their merge subject (#100),
+        // or by their head commit (#8).
+        pull_requests.insert(
+            100,
+            pull_request("master", Some("2015-01-01T12:00:00Z"), None, true),
+        );
+        let mut old_pr = pull_request("staging", Some("2015-01-01T12:00:00Z"), None, true);
+        old_pr["headRefOid"] = json!(fixture.staging_head);
+        pull_requests.insert(8, old_pr);
+
+        let mut rebased_pr = pull_request(
+            "master",
+            Some("2021-05-03T12:00:00Z"),
+            Some(fixture.rebased_head.as_str()),
+            true,
+        );
+        rebased_pr["commits"]["nodes"] = json!([
+            { "commit": { "oid": UNKNOWN_COMMIT, "messageHeadline": "baz: init" } },
+            { "commit": { "oid": UNKNOWN_COMMIT, "messageHeadline": "baz: 1.0 -> 1.1" } },
+        ]);
+        pull_requests.insert(9, rebased_pr);
+
+        let mut reviewed_pr = pull_request("master", None, None, false);
+        reviewed_pr["reviewDecision"] = json!("APPROVED");
+        reviewed_pr["mergeable"] = json!("CONFLICTING");
+        reviewed_pr["labels"] = json!({ "nodes": [{ "name": "6.topic: python" }] });
+        reviewed_pr["latestOpinionatedReviews"] = json!({
+            "nodes": [{ "state": "APPROVED" }, { "state": "APPROVED" }, { "state": "COMMENTED" }],
+        });
+        reviewed_pr["commits"]["nodes"] = json!([{
+            "commit": {
+                "statusCheckRollup": {
+                    "state": "FAILURE",
+                    "contexts": {
+                        "nodes": [
+                            { "__typename": "CheckRun", "name": "eval", "conclusion": "SUCCESS" },
+                            { "__typename": "CheckRun", "name": "build", "conclusion": "FAILURE" },
+                        ],
+                    },
+                },
+            },
+        }]);
+        pull_requests.insert(10, reviewed_pr);
+
+        let mut labelled_pr = pull_request(
+            "staging",
+            Some("2021-05-01T12:00:00Z"),
+            Some(fixture.staging_merge.as_str()),
+            true,
+        );
+        labelled_pr["body"] = json!("Fixes #42.");
+        labelled_pr["milestone"] = json!({ "title": "21.05" });
+        labelled_pr["mergedBy"] = json!({ "__typename": "User", "login": "bob" });
+        labelled_pr["labels"] = json!({
+            "nodes": [{ "name": "1.severity: security" }, { "name": "backport release-20.09" }],
+        });
+        labelled_pr["timelineItems"]["nodes"] = json!([{
+            // Mentions #11, but isn't a backport of it.
+            "__typename": "CrossReferencedEvent",
+            "source": {
+                "__typename": "PullRequest",
+                "number": 16,
+                "title": "foo: fix build on 20.09",
+                "url": "https://github.com/NixOS/nixpkgs/pull/16",
+                "baseRefName": "release-20.09",
+                "headRefName": "foo-fix",
+                "body": "Follow-up to #11.",
+                "merged": true,
+                "closed": true,
+                "mergeCommit": null,
+            },
+        }, {
+            "__typename": "CrossReferencedEvent",
+            "source": {
+                "__typename": "PullRequest",
+                "number": 12,
+                "title": "[Backport release-20.09] foo: 1.0 -> 2.0",
+                "url": "https://github.com/NixOS/nixpkgs/pull/12",
+                "baseRefName": "release-20.09",
+                "headRefName": "backport-11-to-release-20.09",
+                "body": "Bot-based backport to `release-20.09`, triggered by a label in #11.",
+                "merged": false,
+                "closed": false,
+                "mergeCommit": null,
+            },
+        }]);
+        pull_requests.insert(11, labelled_pr);
+
+        pull_requests.insert(
+            7,
+            pull_request(
+                "master",
+                Some("2021-05-02T12:00:00Z"),
+                Some(fixture.reverted_merge.as_str()),
+                true,
+            ),
+        );
+
+        // The same PR as #7, but with its revert going through a PR
+        // (#13), which has itself been reverted, by a PR (#14) that
+        // has only reached master.
+        let mut relanded_pr = pull_requests[&7].clone();
+        relanded_pr["timelineItems"]["nodes"] = json!([{
+            "__typename": "CrossReferencedEvent",
+            "source": {
+                "__typename": "PullRequest",
+                "number": 13,
+                "title": "Revert \"foo: 1.0 -> 2.0\"",
+                "url": "https://github.com/NixOS/nixpkgs/pull/13",
+                "baseRefName": "master",
+                "headRefName": "revert-7-bob/bar",
+                "body": "Reverts NixOS/nixpkgs#15",
+                "merged": true,
+                "closed": true,
+                "mergeCommit": { "oid": fixture.revert },
+            },
+        }]);
+        pull_requests.insert(15, relanded_pr);
+
+        let mut revert_pr = pull_request("master", Some("2021-05-02T13:00:00Z"), None, true);
+        revert_pr["title"] = json!("Revert \"foo: 1.0 -> 2.0\"");
+        revert_pr["timelineItems"]["nodes"] = json!([{
+            "__typename": "CrossReferencedEvent",
+            "source": {
+                "__typename": "PullRequest",
+                "number": 14,
+                "title": "Revert \"Revert \"foo: 1.0 -> 2.0\"\"",
+                "url": "https://github.com/NixOS/nixpkgs/pull/14",
+                "baseRefName": "master",
+                "headRefName": "revert-13-revert-7-bob/bar",
+                "body": "Reverts NixOS/nixpkgs#13",
+                "merged": true,
+                "closed": true,
+                "mergeCommit": { "oid": fixture.rebased_head },
+            },
+        }]);
+        pull_requests.insert(13, revert_pr);
+
+        let api_url = mock_github::start(pull_requests).await;
+
+        Self {
+            fixture,
+            api_url,
+            repository: "NixOS/nixpkgs".parse().unwrap(),
+            token: TokenProvider::Static("token".into()),
+        }
+    }
+
+    fn github(&self) -> GitHub<'_> {
+        GitHub::new(
+            &self.api_url,
+            &self.repository,
+            &self.token,
+            OsStr::new("pr-tracker tests"),
+        )
+    }
+
+    fn nixpkgs(&self) -> Nixpkgs<'_> {
+        Nixpkgs::new(self.fixture.path(), Path::new("nixpkgs"))
+    }
+
+    async fn track(&self, pr_number: &str) -> (u16, PageTemplate) {
+        self.track_with(pr_number, None, &Releases::default(), &Heads::default())
+            .await
+    }
+
+    async fn track_with(
+        &self,
+        pr_number: &str,
+        channels: Option<&Channels<'_>>,
+        releases: &Releases,
+        heads: &Heads,
+    ) -> (u16, PageTemplate) {
+        let mut status = 200;
+        let mut page = PageTemplate::default();
+        let context = Context {
+            forge: &self.github(),
+            nixpkgs: &self.nixpkgs(),
+            channels,
+            hydra_url: Some("https://hydra.nixos.org"),
+            releases,
+            heads,
+        };
+        track_pr(context, Some(pr_number.to_string()), &mut status, &mut page).await;
+
+        (status, page)
+    }
+
+    async fn compare(&self, from: &str, to: &str) -> (u16, compare::CompareTemplate) {
+        let mut status = 200;
+        let mut page = compare::CompareTemplate::default();
+        compare::compare(
+            &self.github(),
+            &self.nixpkgs(),
+            from,
+            to,
+            &mut status,
+            &mut page,
+        )
+        .await;
+        (status, page)
+    }
+}
+
+/// Reduce a serialized tree to just the fields in `node`.
+fn states(tree: &Tree) -> Value {
+    fn strip(tree: &Value) -> Value {
+        let children = tree["children"].as_array().unwrap();
+        json!({
+            "branch_name": tree["branch_name"],
+            "accepted": tree["accepted"],
+            "children": children.iter().map(strip).collect::<Vec<_>>(),
+        })
+    }
+
+    strip(&serde_json::to_value(tree).unwrap())
+}
+
+fn node(branch_name: &str, accepted: Option<bool>, children: Vec<Value>) -> Value {
+    json!({
+        "branch_name": branch_name,
+        "accepted": accepted,
+        "children": children,
+    })
+}
+
+/// The tree for a PR to master, with every branch in the same state.
+fn master_tree(master: Option<bool>, rest: Option<bool>) -> Value {
+    node(
+        "master",
+        master,
+        vec![
+            node("nixpkgs-unstable", rest, vec![]),
+            node(
+                "nixos-unstable-small",
+                rest,
+                vec![node("nixos-unstable", rest, vec![])],
+            ),
+        ],
+    )
+}
+
+/// Extract (branch, state) pairs from the tree in a rendered page.
+fn rendered_tree(page: &PageTemplate) -> Vec<(String, String)> {
+    let re =
+        Regex::new(r#"<span class="state-(\w+)">[^<]*</span>\s*(?:<a [^>]*>)?([^\s<]+)"#).unwrap();
+    let html = page.render().unwrap();
+    re.captures_iter(&html)
+        .map(|c| (c[2].to_string(), c[1].to_string()))
+        .collect()
+}
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(branch, state)| (branch.to_string(), state.to_string()))
+        .collect()
+}
+
+#[async_std::test]
+async fn open() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("1").await;
+
+    assert_eq!(status, 200);
+    assert!(!page.closed);
+    assert_eq!(
+        states(page.tree.as_ref().unwrap()),
+        master_tree(Some(false), Some(false))
+    );
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("master", "pending"),
+            ("nixpkgs-unstable", "pending"),
+            ("nixos-unstable-small", "pending"),
+            ("nixos-unstable", "pending"),
+        ])
+    );
+}
+
+#[async_std::test]
+async fn review() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("10").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(page.labels, vec!["6.topic: python"]);
+
+    let review = page.review.as_ref().unwrap();
+    assert!(!review.draft);
+    assert_eq!(review.decision.as_deref(), Some("approved"));
+    assert_eq!(review.approvals, 2);
+    assert!(review.conflicting);
+    assert_eq!(review.checks.as_deref(), Some("failing"));
+    assert_eq!(review.failing_checks, vec!["build"]);
+
+    let html = page.render().unwrap();
+    assert!(html.contains("approved, 2 approvals"));
+    assert!(html.contains("failing: build"));
+    assert!(html.contains("has merge conflicts"));
+}
+
+#[async_std::test]
+async fn metadata() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("11").await;
+
+    assert_eq!(status, 200);
+    assert!(page.review.is_none());
+    assert_eq!(page.milestone.as_deref(), Some("21.05"));
+    assert_eq!(page.merged_by.as_deref(), Some("bob"));
+    assert_eq!(page.merged_at.as_deref(), Some("2021-05-01T12:00:00Z"));
+    assert_eq!(page.backports, vec!["release-20.09"]);
+    assert_eq!(page.closes.len(), 1);
+    assert_eq!(
+        page.closes[0].url,
+        format!("{}/NixOS/nixpkgs/issues/42", setup.api_url)
+    );
+
+    let html = page.render().unwrap();
+    assert!(html.contains("2021-05-01T12:00:00Z by bob"));
+    assert!(html.contains(">#42</a>"));
+    assert!(html.contains("1.severity: security, backport release-20.09"));
+}
+
+#[async_std::test]
+async fn backports() {
+    let setup = Setup::new().await;
+    let (_, page) = setup.track("11").await;
+
+    let tree = page.tree.as_ref().unwrap();
+    let backport = tree.find("release-20.09").unwrap();
+    assert_eq!(backport.accepted, Some(false));
+    assert_eq!(
+        backport.backport.as_ref().unwrap().description(),
+        "backport PR #12 open"
+    );
+    assert!(tree.find("nixos-20.09").is_some());
+
+    let html = page.render().unwrap();
+    assert!(html.contains(">backport PR #12 open</a>"));
+}
+
+#[async_std::test]
+async fn end_of_life() {
+    let setup = Setup::new().await;
+
+    // 20.09 has only just reached its end of life, so the backport is
+    // still shown, but isn't pending any more.
+    let releases = Releases::new(&[EndOfLife {
+        version: "20.09".to_string(),
+        date: SystemTime::now() - Duration::from_secs(24 * 60 * 60),
+    }]);
+    let (_, page) = setup
+        .track_with("11", None, &releases, &Heads::default())
+        .await;
+
+    let tree = page.tree.as_ref().unwrap();
+    for branch in &["release-20.09", "nixos-20.09"] {
+        assert_eq!(tree.find(branch).unwrap().state(), State::EndOfLife);
+    }
+    assert!(page.render().unwrap().contains("(end of life)"));
+
+    // Once it's long gone, there's no point showing it at all.
+    let releases = Releases::new(&["20.09=2021-06-30".parse().unwrap()]);
+    let (_, page) = setup
+        .track_with("11", None, &releases, &Heads::default())
+        .await;
+    assert!(page.tree.as_ref().unwrap().find("release-20.09").is_none());
+}
+
+#[async_std::test]
+async fn merged() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("2").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    assert_eq!(
+        states(page.tree.as_ref().unwrap()),
+        node(
+            "staging",
+            Some(true),
+            vec![node(
+                "staging-next",
+                Some(true),
+                vec![node(
+                    "master",
+                    Some(true),
+                    vec![
+                        node("nixpkgs-unstable", Some(true), vec![]),
+                        node(
+                            "nixos-unstable-small",
+                            Some(true),
+                            vec![node("nixos-unstable", Some(false), vec![])],
+                        ),
+                    ],
+                )],
+            )],
+        )
+    );
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("staging", "accepted"),
+            ("staging-next", "accepted"),
+            ("master", "accepted"),
+            ("nixpkgs-unstable", "accepted"),
+            ("nixos-unstable-small", "accepted"),
+            ("nixos-unstable", "pending"),
+        ])
+    );
+
+    let tree = serde_json::to_value(page.tree.as_ref().unwrap()).unwrap();
+    let foo = |version: &str| json!([{ "attr": "foo", "before": "1.0", "version": version }]);
+    assert_eq!(tree["packages"], foo("2.0"));
+    let nixos_unstable_small = &tree["children"][0]["children"][0]["children"][1];
+    assert_eq!(nixos_unstable_small["packages"], foo("2.0"));
+    assert_eq!(nixos_unstable_small["children"][0]["packages"], foo("1.0"));
+}
+
+#[async_std::test]
+async fn rewritten() {
+    let setup = Setup::new().await;
+    let nixpkgs = setup.nixpkgs();
+
+    // nixos-unstable was seen with the change, before being reset to
+    // where it is now.
+    let mut heads = Heads::default();
+    let before = nixpkgs::Head {
+        branch: "nixos-unstable".to_string(),
+        commit: setup.fixture.staging_next_merge.clone(),
+        committed: String::new(),
+    };
+    heads.observe(&nixpkgs, &[before]).await;
+    let after = nixpkgs.heads().await.unwrap();
+    heads.observe(&nixpkgs, &after).await;
+
+    let (status, page) = setup
+        .track_with("2", None, &Releases::default(), &heads)
+        .await;
+
+    assert_eq!(status, 200);
+    let rewritten = |branch| page.tree.as_ref()?.find(branch)?.rewritten.as_ref();
+    assert!(rewritten("nixos-unstable").is_some());
+    assert!(rewritten("nixos-unstable-small").is_none());
+    assert!(page.render().unwrap().contains("branch was rewritten at"));
+}
+
+#[async_std::test]
+async fn closed() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("3").await;
+
+    assert_eq!(status, 200);
+    assert!(page.closed);
+    assert!(page.tree.is_none());
+    assert!(page.render().unwrap().contains("(closed)"));
+}
+
+#[async_std::test]
+async fn null_merge_commit() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("4").await;
+
+    assert_eq!(status, 200);
+    assert!(page.error.is_some());
+    assert_eq!(
+        states(page.tree.as_ref().unwrap()),
+        master_tree(Some(true), None)
+    );
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("master", "accepted"),
+            ("nixpkgs-unstable", "unknown"),
+            ("nixos-unstable-small", "unknown"),
+            ("nixos-unstable", "unknown"),
+        ])
+    );
+}
+
+#[async_std::test]
+async fn resolved_merge_commit() {
+    let setup = Setup::new().await;
+
+    let (status, page) = setup.track("100").await;
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    let mut expected = master_tree(Some(true), Some(true));
+    expected["children"][1]["children"][0]["accepted"] = json!(false);
+    assert_eq!(states(page.tree.as_ref().unwrap()), expected);
+
+    let (status, page) = setup.track("8").await;
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    let tree = serde_json::to_value(page.tree.as_ref().unwrap()).unwrap();
+    assert_eq!(tree["branch_name"], "staging");
+    assert_eq!(tree["children"][0]["accepted"], true);
+}
+
+#[async_std::test]
+async fn unknown_commit() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("5").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    assert_eq!(
+        states(page.tree.as_ref().unwrap()),
+        master_tree(Some(true), None)
+    );
+}
+
+#[async_std::test]
+async fn reverted() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("7").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("master", "reverted"),
+            ("nixpkgs-unstable", "reverted"),
+            ("nixos-unstable-small", "reverted"),
+            ("nixos-unstable", "pending"),
+        ])
+    );
+
+    let tree = serde_json::to_value(page.tree.as_ref().unwrap()).unwrap();
+    assert_eq!(tree["accepted"], true);
+    assert_eq!(tree["reverted"], true);
+    assert!(page.ogmeta.unwrap().render().unwrap().contains("↩️ master"));
+}
+
+#[async_std::test]
+async fn relanded() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("15").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("master", "accepted"),
+            ("nixpkgs-unstable", "reverted"),
+            ("nixos-unstable-small", "accepted"),
+            ("nixos-unstable", "pending"),
+        ])
+    );
+
+    let tree = serde_json::to_value(page.tree.as_ref().unwrap()).unwrap();
+    assert_eq!(tree["reverted"], false);
+}
+
+#[async_std::test]
+async fn partial() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("9").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("master", "accepted"),
+            ("nixpkgs-unstable", "partial"),
+            ("nixos-unstable-small", "accepted"),
+            ("nixos-unstable", "pending"),
+        ])
+    );
+    assert!(page.render().unwrap().contains("(partially present)"));
+}
+
+#[async_std::test]
+async fn carriers() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("2").await;
+
+    assert_eq!(status, 200);
+    let tree = page.tree.as_ref().unwrap();
+
+    let staging_next = tree.find("staging-next").unwrap();
+    let carrier = staging_next.carried_by.as_ref().unwrap();
+    assert_eq!(carrier.commit, setup.fixture.staging_next_merge);
+    assert_eq!(carrier.pr_number, Some(100));
+    let hydra_url = format!(
+        "https://hydra.nixos.org/search?query={}",
+        setup.fixture.staging_next_merge
+    );
+    assert_eq!(carrier.hydra_url, Some(hydra_url));
+
+    let master = tree.find("master").unwrap();
+    let carrier = master.carried_by.as_ref().unwrap();
+    assert_eq!(carrier.pr_number, Some(100));
+    assert_eq!(carrier.title.as_deref(), Some("staging-next 2021-05-01"));
+
+    assert!(tree.find("staging").unwrap().carried_by.is_none());
+    assert!(tree
+        .find("nixos-unstable-small")
+        .unwrap()
+        .carried_by
+        .is_none());
+}
+
+#[async_std::test]
+async fn links() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("2").await;
+
+    assert_eq!(status, 200);
+    let tree = page.tree.as_ref().unwrap();
+    // The mock API is on the same host as the website would be.
+    let repository = format!("{}/NixOS/nixpkgs", setup.api_url);
+
+    let staging = tree.find("staging").unwrap();
+    assert_eq!(
+        staging.branch_url.as_deref(),
+        Some(format!("{}/tree/staging", repository).as_str())
+    );
+    let link = staging.commit_link.as_ref().unwrap();
+    assert_eq!(
+        link.url,
+        format!("{}/commit/{}", repository, setup.fixture.staging_merge)
+    );
+    assert_eq!(link.text, &setup.fixture.staging_merge[..12]);
+
+    let staging_next = tree.find("staging-next").unwrap();
+    assert_eq!(
+        staging_next.commit_link.as_ref().unwrap().url,
+        format!("{}/commit/{}", repository, setup.fixture.staging_next_merge)
+    );
+
+    let nixos_unstable = tree.find("nixos-unstable").unwrap();
+    let link = nixos_unstable.commit_link.as_ref().unwrap();
+    assert_eq!(
+        link.url,
+        format!(
+            "{}/compare/nixos-unstable...{}",
+            repository, setup.fixture.staging_merge
+        )
+    );
+    assert_eq!(link.text, "compare");
+}
+
+#[async_std::test]
+async fn unreleased() {
+    let setup = Setup::new().await;
+
+    // nixpkgs-unstable has been released since the PR reached it, but
+    // nixos-unstable-small hasn't.
+    let mut revisions = BTreeMap::new();
+    revisions.insert(
+        "nixpkgs-unstable".to_string(),
+        setup.fixture.reverted_merge.clone(),
+    );
+    revisions.insert(
+        "nixos-unstable-small".to_string(),
+        setup.fixture.staging_head.clone(),
+    );
+    let channels_url = mock_channels::start(revisions).await;
+    let channels = Channels::new(&channels_url, OsStr::new("pr-tracker tests"));
+
+    let (status, page) = setup
+        .track_with(
+            "2",
+            Some(&channels),
+            &Releases::default(),
+            &Heads::default(),
+        )
+        .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(
+        rendered_tree(&page),
+        pairs(&[
+            ("staging", "accepted"),
+            ("staging-next", "accepted"),
+            ("master", "accepted"),
+            ("nixpkgs-unstable", "accepted"),
+            ("nixos-unstable-small", "unreleased"),
+            ("nixos-unstable", "pending"),
+        ])
+    );
+
+    let tree = page.tree.as_ref().unwrap();
+    let node = tree.find("nixos-unstable-small").unwrap();
+    assert_eq!(node.released.as_deref(), Some(mock_channels::RELEASED));
+    assert!(!tree.fully_accepted());
+}
+
+#[async_std::test]
+async fn not_found() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("6").await;
+
+    assert_eq!(status, 404);
+    assert!(page.tree.is_none());
+}
+
+#[async_std::test]
+async fn invalid() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.track("six").await;
+
+    assert_eq!(status, 400);
+    assert!(page.error.is_some());
+}
+
+#[async_std::test]
+async fn watched() {
+    let setup = Setup::new().await;
+    let (github, nixpkgs) = (setup.github(), setup.nixpkgs());
+    let releases = Releases::default();
+
+    let open = watch::watched_pr(&github, &nixpkgs, &releases, 1).await;
+    assert_eq!(open.in_unstable, Some(false));
+    assert!(!open.done);
+
+    let merged = watch::watched_pr(&github, &nixpkgs, &releases, 2).await;
+    assert_eq!(merged.error, None);
+    assert_eq!(merged.in_unstable, Some(false));
+    assert!(!merged.done);
+
+    let closed = watch::watched_pr(&github, &nixpkgs, &releases, 3).await;
+    assert!(closed.closed);
+
+    let not_found = watch::watched_pr(&github, &nixpkgs, &releases, 6).await;
+    assert!(not_found.error.is_some());
+}
+
+#[async_std::test]
+async fn path_history() {
+    let setup = Setup::new().await;
+    let path = "pkgs/by-name/fo/foo/package.nix";
+    let (changes, more) = history::changes_to_path(&setup.nixpkgs(), path, 0, 10)
+        .await
+        .unwrap();
+
+    // The merges of staging into staging-next and of staging-next
+    // into master are left out, because they only carried the PR.
+    let numbers: Vec<_> = changes.iter().map(|change| change.pr_number).collect();
+    assert_eq!(numbers, vec![Some(2), None]);
+    assert_eq!(changes[0].commit, setup.fixture.staging_merge);
+    assert!(!more);
+}
+
+#[async_std::test]
+async fn compare_branches() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.compare("master", "nixos-unstable").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(page.error, None);
+    let numbers: Vec<_> = page.prs.iter().map(|pr| pr.pr_number).collect();
+    // #9 was rebased, so it's only found by asking GitHub.
+    assert_eq!(numbers, vec![Some(9), Some(7), Some(100), Some(2)]);
+    assert_eq!(page.prs[0].author.as_deref(), Some("alice"));
+    assert_eq!(page.prs[0].head_owner.as_deref(), Some("alice"));
+    assert_eq!(page.prs[2].head_owner.as_deref(), Some("NixOS"));
+}
+
+#[async_std::test]
+async fn compare_up_to_date() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.compare("staging-next", "master").await;
+
+    assert_eq!(status, 200);
+    assert!(page.prs.is_empty());
+}
+
+#[async_std::test]
+async fn compare_unrelated() {
+    let setup = Setup::new().await;
+    let (status, page) = setup.compare("master", "staging").await;
+
+    assert_eq!(status, 400);
+    assert!(page.error.is_some());
+}
+
+#[async_std::test]
+async fn branch_graph() {
+    let setup = Setup::new().await;
+    let roots = overview::branch_graph(&setup.nixpkgs(), &Heads::default(), &Releases::default())
+        .await
+        .unwrap();
+
+    let names: Vec<_> = roots.iter().map(|root| root.name.as_str()).collect();
+    assert_eq!(names, vec!["staging"]);
+
+    let json = serde_json::to_value(&roots[0]).unwrap();
+    let master = &json["children"][0]["children"][0];
+    assert_eq!(master["name"], "master");
+    assert_eq!(master["behind"], 0);
+    assert_eq!(master["shared"], false);
+
+    let nixos_unstable = &master["children"][1]["children"][0];
+    assert_eq!(nixos_unstable["name"], "nixos-unstable");
+    assert_eq!(nixos_unstable["behind"], 9);
+}
+
+#[async_std::test]
+async fn og_image() {
+    let setup = Setup::new().await;
+    let (_, page) = setup.track("2").await;
+
+    let image = og_image::OgImage::new(2, "foo: 1.0 -> 2.0", page.tree.as_ref());
+    let svg = image.render().unwrap();
+    assert!(svg.contains("#2 foo: 1.0 -&gt; 2.0"));
+    assert_eq!(svg.matches("<circle").count(), 6);
+    assert!(svg.contains(">nixos-unstable<"));
+
+    let png = image.render_png().await.unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+    assert_eq!(image.render_png().await.unwrap(), png);
+}
+
+#[async_std::test]
+async fn export() {
+    let setup = Setup::new().await;
+    let (_, page) = setup.track("2").await;
+
+    let merged_at = page.merged_at.as_deref();
+    let dot = export::dot("2", merged_at, page.tree.as_ref());
+    assert!(dot.starts_with("digraph \"PR #2\" {\n"));
+    assert!(dot.contains(
+        "  n0 [label=\"staging\\nmerged 2021-05-01T12:00:00Z\", class=\"state-accepted\", fillcolor=\"#00C42D\"];\n"
+    ));
+    assert!(dot.contains(
+        "  n5 [label=\"nixos-unstable\", class=\"state-pending\", fillcolor=\"#C2C9C2\"];\n"
+    ));
+    assert!(dot.contains("  n4 -> n5;\n"));
+
+    let mermaid = export::mermaid(merged_at, page.tree.as_ref());
+    assert!(mermaid.starts_with("flowchart TD\n"));
+    assert!(mermaid.contains("  n0[\"staging<br>merged 2021-05-01T12:00:00Z\"]:::accepted\n"));
+    assert!(mermaid.contains("  n5[\"nixos-unstable\"]:::pending\n"));
+    assert!(mermaid.contains("  n4 --> n5\n"));
+    assert!(mermaid.contains("  classDef pending fill:#C2C9C2\n"));
+
+    let closed = export::mermaid(None, None);
+    assert!(closed.contains("  n0[\"closed\"]:::rejected\n"));
+}
+
+#[async_std::test]
+async fn export_error() {
+    let page = PageTemplate {
+        pr_number: Some("2".to_string()),
+        error: Some("No tree".to_string()),
+        ..Default::default()
+    };
+
+    let mut response = export_tree("dot", 500, &page, false);
+    assert_eq!(response.status(), 500);
+    let body = response.take_body().into_string().await.unwrap();
+    assert_eq!(body, "No tree\n");
+}
+
+#[test]
+fn negotiation() {
+    let firefox = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+    assert_eq!(negotiate(Some(firefox), Some("Mozilla/5.0")), "html");
+    assert_eq!(negotiate(Some("*/*"), Some("curl/7.76.1")), "text");
+    assert_eq!(negotiate(Some("*/*"), Some("Slackbot 1.0")), "html");
+    assert_eq!(negotiate(None, None), "html");
+    assert_eq!(
+        negotiate(Some("application/json"), Some("curl/7.76.1")),
+        "json"
+    );
+    assert_eq!(
+        negotiate(Some("text/html;q=0.5, text/plain"), Some("Mozilla/5.0")),
+        "text"
+    );
+}
+
+#[async_std::test]
+async fn text() {
+    let setup = Setup::new().await;
+    let (_, page) = setup.track("2").await;
+
+    let text = export::text(
+        "2",
+        Some("foo: 1.0 -> 2.0"),
+        None,
+        page.tree.as_ref(),
+        false,
+    );
+    assert_eq!(
+        text,
+        "PR #2: foo: 1.0 -> 2.0
+
+✅ staging
+└─ ✅ staging-next
+   └─ ✅ master
+      ├─ ✅ nixpkgs-unstable
+      └─ ✅ nixos-unstable-small
+         └─ ⚪ nixos-unstable
+"
+    );
+
+    let coloured = export::text("2", None, None, page.tree.as_ref(), true);
+    assert!(coloured.contains("└─ ⚪ \x1b[2mnixos-unstable\x1b[0m\n"));
+}
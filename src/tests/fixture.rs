@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use tempfile::TempDir;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("HOME", dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "pr-tracker")
+        .env("GIT_AUTHOR_EMAIL", "pr-tracker@example.com")
+        .env("GIT_COMMITTER_NAME", "pr-tracker")
+        .env("GIT_COMMITTER_EMAIL", "pr-tracker@example.com")
+        .output()
+        .expect("running git");
+
+    assert!(
+        output.status.success(),
+        "git {:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+fn commit(dir: &Path, message: &str) -> String {
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-q", "--allow-empty", "-m", message]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+fn write_package(dir: &Path, version: &str) {
+    let package_dir = dir.join("pkgs/by-name/fo/foo");
+    let expression = format!("{{\n  pname = \"foo\";\n  version = \"{}\";\n}}\n", version);
+    fs::create_dir_all(&package_dir).unwrap();
+    fs::write(package_dir.join("package.nix"), expression).unwrap();
+}
+
+/// Check out `into`, and merge `branch` into it with a merge commit.
+fn merge(dir: &Path, into: &str, branch: &str, message: &str) -> String {
+    git(dir, &["checkout", "-q", into]);
+    git(dir, &["merge", "-q", "--no-ff", "-m", message, branch]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// A checkout with a remote, "nixpkgs", whose history contains a PR
+/// merged into staging, that has been promoted through staging-next,
+/// master and nixos-unstable-small, but not yet nixos-unstable.  The
+/// PR updates the "foo" package from 1.0 to 2.0.  After that, a PR
+/// merged into master was reverted there, with `git revert`, and a
+/// two-commit PR was rebased onto master, with nixpkgs-unstable
+/// only getting as far as its first commit.
+pub struct Fixture {
+    _upstream: TempDir,
+    checkout: TempDir,
+
+    /// The last commit of the PR that was merged into staging.
+    pub staging_head: String,
+
+    /// The merge commit of the PR that was merged into staging.
+    pub staging_merge: String,
+
+    /// The merge of staging into staging-next that brought in the PR
+    /// that was merged into staging.
+    pub staging_next_merge: String,
+
+    /// The merge commit of the PR that was reverted on master.
+    pub reverted_merge: String,
+
+    /// The commit that reverted it.
+    pub revert: String,
+
+    /// The last commit of the PR that was rebased onto master.
+    pub rebased_head: String,
+
+    /// A commit in the checkout that isn't on any branch, like the
+    /// head of a PR that was fetched but never merged.
+    pub unmerged_head: String,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let upstream = TempDir::new().unwrap();
+        let up = upstream.path();
+
+        git(up, &["init", "-q"]);
+        git(up, &["symbolic-ref", "HEAD", "refs/heads/master"]);
+        write_package(up, "1.0");
+        commit(up, "init");
+        git(up, &["branch", "staging"]);
+        git(up, &["branch", "staging-next"]);
+        git(up, &["branch", "nixos-unstable"]);
+
+        git(up, &["checkout", "-q", "-b", "alice/foo", "staging"]);
+        write_package(up, "2.0");
+        let staging_head = commit(up, "foo: 1.0 -> 2.0");
+
+        let staging_merge = merge(
+            up,
+            "staging",
+            "alice/foo",
+            "Merge pull request #2 from alice/foo",
+        );
+        let staging_next_merge = merge(
+            up,
+            "staging-next",
+            "staging",
+            "Merge branch 'staging' into staging-next",
+        );
+        merge(
+            up,
+            "master",
+            "staging-next",
+            "Merge pull request #100 from NixOS/staging-next\n\nstaging-next 2021-05-01",
+        );
+
+        git(up, &["checkout", "-q", "-b", "bob/bar", "master"]);
+        fs::write(up.join("bar"), "bar\n").unwrap();
+        commit(up, "bar: init");
+        let reverted_merge = merge(
+            up,
+            "master",
+            "bob/bar",
+            "Merge pull request #7 from bob/bar",
+        );
+        git(up, &["revert", "--no-edit", "-m", "1", &reverted_merge]);
+        let revert = git(up, &["rev-parse", "HEAD"]);
+
+        fs::write(up.join("baz"), "1.0\n").unwrap();
+        commit(up, "baz: init");
+        git(up, &["branch", "nixpkgs-unstable", "master"]);
+        fs::write(up.join("baz"), "1.1\n").unwrap();
+        let rebased_head = commit(up, "baz: 1.0 -> 1.1");
+
+        git(up, &["branch", "nixos-unstable-small", "master"]);
+
+        let checkout = TempDir::new().unwrap();
+        let co = checkout.path();
+
+        git(co, &["init", "-q"]);
+        git(co, &["remote", "add", "nixpkgs", up.to_str().unwrap()]);
+        git(co, &["fetch", "-q", "nixpkgs"]);
+        let tree = format!("{}^{{tree}}", rebased_head);
+        let unmerged_head = git(
+            co,
+            &["commit-tree", &tree, "-p", &rebased_head, "-m", "qux: init"],
+        );
+
+        Self {
+            _upstream: upstream,
+            checkout,
+            staging_head,
+            staging_merge,
+            staging_next_merge,
+            reverted_merge,
+            revert,
+            rebased_head,
+            unmerged_head,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.checkout.path()
+    }
+}
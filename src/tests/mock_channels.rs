@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_std::net::TcpListener;
+use tide::{Request, Response};
+
+/// When every channel was released.
+pub const RELEASED: &str = "Sat, 01 May 2021 12:00:00 GMT";
+
+type Revisions = Arc<BTreeMap<String, String>>;
+
+/// Serve the released commit of each channel, like
+/// channels.nixos.org, from a local HTTP server.  Returns the base
+/// URL to give to `Channels::new`.
+pub async fn start(revisions: BTreeMap<String, String>) -> String {
+    let mut server = tide::with_state(Arc::new(revisions));
+    server.at("/:channel/git-revision").get(git_revision);
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    async_std::task::spawn(server.listen(listener));
+    url
+}
+
+async fn git_revision(request: Request<Revisions>) -> tide::Result<Response> {
+    let channel = request.param("channel")?;
+    Ok(match request.state().get(channel) {
+        Some(commit) => Response::builder(200)
+            .header("Last-Modified", RELEASED)
+            .body(format!("{}\n", commit))
+            .build(),
+        None => Response::new(404),
+    })
+}
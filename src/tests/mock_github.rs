@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_std::net::TcpListener;
+use serde_json::{json, Value};
+use tide::{Body, Request};
+
+type PullRequests = Arc<BTreeMap<i64, Value>>;
+
+/// Serve canned responses to PrInfoQuery, keyed by PR number, from a
+/// local HTTP server.  Returns the API URL to give to `GitHub::new`.
+pub async fn start(pull_requests: BTreeMap<i64, Value>) -> String {
+    let mut server = tide::with_state(Arc::new(pull_requests));
+    server.at("/graphql").post(graphql);
+    server
+        .at("/repos/:owner/:repo/commits/:commit/pulls")
+        .get(commit_pulls);
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    async_std::task::spawn(server.listen(listener));
+    url
+}
+
+async fn graphql(mut request: Request<PullRequests>) -> tide::Result<Body> {
+    let query: Value = request.body_json().await?;
+    let number = query["variables"]["number"].as_i64().unwrap_or_default();
+    let mut pull_request = request.state().get(&number).cloned().unwrap_or(Value::Null);
+    if let Value::Object(pr) = &mut pull_request {
+        let url = format!("https://github.com/NixOS/nixpkgs/pull/{}", number);
+        pr.insert("number".to_string(), Value::from(number));
+        pr.insert("url".to_string(), Value::String(url));
+    }
+
+    // Give out timelines one item at a time, so that paging through
+    // them is exercised.
+    let cursor: usize = query["variables"]["cursor"]
+        .as_str()
+        .and_then(|cursor| cursor.parse().ok())
+        .unwrap_or_default();
+    if let Some(timeline) = pull_request.get_mut("timelineItems") {
+        let nodes = timeline["nodes"].as_array().cloned().unwrap_or_default();
+        timeline["nodes"] = Value::Array(nodes.get(cursor).cloned().into_iter().collect());
+        timeline["pageInfo"] = json!({
+            "hasNextPage": cursor + 1 < nodes.len(),
+            "endCursor": (cursor + 1).to_string(),
+        });
+    }
+
+    Body::from_json(&json!({
+        "data": {
+            "repository": {
+                "pullRequest": pull_request,
+            },
+        },
+    }))
+}
+
+/// The merged PRs whose merge commit is `commit`, in the form the
+/// REST API gives them.
+async fn commit_pulls(request: Request<PullRequests>) -> tide::Result<Body> {
+    let commit = request.param("commit")?;
+    let prs: Vec<_> = request
+        .state()
+        .iter()
+        .filter(|(_, pr)| pr["merged"] == true && pr["mergeCommit"]["oid"] == commit)
+        .map(|(number, pr)| {
+            json!({
+                "number": number,
+                "title": pr["title"],
+                "user": { "login": pr["author"]["login"] },
+                "head": { "repo": { "owner": { "login": pr["author"]["login"] } } },
+                "merged_at": pr["mergedAt"],
+            })
+        })
+        .collect();
+
+    Body::from_json(&prs)
+}
+
+pub fn pull_request(
+    base: &str,
+    merged_at: Option<&str>,
+    merge_commit: Option<&str>,
+    closed: bool,
+) -> Value {
+    json!({
+        "title": "foo: 1.0 -> 2.0",
+        "author": {
+            "__typename": "User",
+            "login": "alice",
+            "url": "https://github.com/alice",
+        },
+        "baseRefName": base,
+        "headRefOid": "1111111111111111111111111111111111111111",
+        "mergeCommit": merge_commit.map(|oid| json!({ "oid": oid })),
+        "merged": merged_at.is_some(),
+        "mergedAt": merged_at,
+        "closed": closed,
+        "body": "",
+        "isDraft": false,
+        "mergeable": "MERGEABLE",
+        "commits": { "nodes": [] },
+        "timelineItems": { "nodes": [] },
+    })
+}
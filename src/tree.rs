@@ -1,39 +1,322 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
 // SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display, Formatter};
 
 use askama::Template;
+use serde::Serialize;
 
-use crate::branches::next_branches;
-use crate::github;
-use crate::nixpkgs::Nixpkgs;
+use crate::branches::{self, Graph};
+use crate::channels::{is_channel, Channels};
+use crate::forge::{self, WebLinks};
+use crate::heads::Heads;
+use crate::history::Change;
+use crate::nixpkgs::{self, Nixpkgs};
+use crate::packages::{self, PackageVersion};
+use crate::releases::Releases;
 
-#[derive(Debug, Template)]
+/// The merge that brought a change into a branch from the one before
+/// it.
+#[derive(Debug, Serialize)]
+pub struct Carrier {
+    pub commit: String,
+
+    /// The PR that the merge was part of, e.g. "staging-next
+    /// 2021-05-01", and its title.
+    pub pr_number: Option<i64>,
+    pub title: Option<String>,
+
+    /// Hydra's evaluation of the merge, if we know where Hydra is.
+    pub hydra_url: Option<String>,
+}
+
+impl Carrier {
+    pub fn short_commit(&self) -> &str {
+        self.commit.get(..12).unwrap_or(&self.commit)
+    }
+}
+
+fn is_staging_next(branch: &str) -> bool {
+    branch == "staging-next" || branch.starts_with("staging-next-")
+}
+
+/// The branches that contain any of `commits`.
+async fn branches_containing(commits: &[String], nixpkgs: &Nixpkgs<'_>) -> BTreeSet<OsString> {
+    let mut branches = BTreeSet::new();
+    for commit in commits {
+        if let Err(e) = nixpkgs
+            .branches_containing_commit(commit, &mut branches)
+            .await
+        {
+            eprintln!("pr-tracker: branches_containing_commit: {}", e);
+        }
+    }
+    branches
+}
+
+/// A link to the first commit on a branch to contain a change, or,
+/// if the branch doesn't have it yet, to a comparison of the branch
+/// with the change.
+#[derive(Debug, Serialize)]
+pub struct CommitLink {
+    pub url: String,
+    pub text: String,
+}
+
+/// How far a backport has got.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackportStatus {
+    /// There's no backport PR yet.
+    Pending,
+
+    Open,
+    Merged,
+}
+
+impl Display for BackportStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pending => "pending",
+            Self::Open => "open",
+            Self::Merged => "merged",
+        })
+    }
+}
+
+/// A backport that a PR is labelled as needing.
+#[derive(Debug, Serialize)]
+pub struct Backport {
+    /// The backport PR, if one has been opened.
+    pub number: Option<i64>,
+    pub url: Option<String>,
+
+    pub status: BackportStatus,
+}
+
+impl Backport {
+    pub fn description(&self) -> String {
+        match self.number {
+            Some(number) => format!("backport PR #{} {}", number, self.status),
+            None => format!("backport {}", self.status),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Template)]
 #[template(path = "tree.html")]
 pub struct Tree {
     pub branch_name: String,
     pub accepted: Option<bool>,
+
+    /// Whether the branch also comes after another branch earlier in
+    /// the tree, where its children are shown instead.
+    pub shared: bool,
+
+    /// Whether the change reached this branch, but was then reverted
+    /// on it.
+    pub reverted: bool,
+
+    /// Whether only some of the change's commits have reached this
+    /// branch.
+    pub partial: bool,
+
+    /// Whether the branch's release is no longer supported, so the
+    /// change won't reach it if it hasn't already.
+    pub end_of_life: bool,
+
+    /// Whether the change is on this channel branch, but not yet in
+    /// the channel's latest release.
+    pub unreleased: bool,
+
+    /// When the channel was last released, for channel branches.
+    pub released: Option<String>,
+
+    /// When the branch was rewritten, if the change was on it before
+    /// that, but isn't any more.
+    pub rewritten: Option<String>,
+
+    /// For the root of a backport's subtree, how the backport is
+    /// going.
+    pub backport: Option<Backport>,
+
+    /// For the branches on either side of a staging-next cycle, the
+    /// merge that brought the change in.
+    pub carried_by: Option<Carrier>,
+
+    pub branch_url: Option<String>,
+
+    /// Where the change is on the branch, or what the branch is still
+    /// missing of it.
+    pub commit_link: Option<CommitLink>,
+
+    pub packages: Vec<PackageVersion>,
     pub children: Vec<Tree>,
 }
 
+/// How far a change has got with a branch, as shown for each node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Accepted,
+    Pending,
+    Unknown,
+
+    /// For a closed PR, which has no tree.
+    Rejected,
+
+    Reverted,
+    Partial,
+    Unreleased,
+    EndOfLife,
+}
+
+impl State {
+    pub const ALL: [State; 8] = [
+        State::Accepted,
+        State::Pending,
+        State::Unknown,
+        State::Rejected,
+        State::Reverted,
+        State::Partial,
+        State::Unreleased,
+        State::EndOfLife,
+    ];
+
+    /// The state's name, as used in the state-* classes in
+    /// page.html.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Pending => "pending",
+            Self::Unknown => "unknown",
+            Self::Rejected => "rejected",
+            Self::Reverted => "reverted",
+            Self::Partial => "partial",
+            Self::Unreleased => "unreleased",
+            Self::EndOfLife => "eol",
+        }
+    }
+
+    /// The fill colour for the state, as used by the state-* classes
+    /// in page.html.
+    pub fn colour(self) -> &'static str {
+        match self {
+            Self::Accepted => "#00C42D",
+            Self::Pending => "#C2C9C2",
+            Self::Unknown => "#C4A500",
+            Self::Rejected => "#c40000",
+            Self::Reverted => "#C46A00",
+            Self::Partial => "#61C777",
+            Self::Unreleased => "#4F9FD9",
+            Self::EndOfLife => "#5C5C5C",
+        }
+    }
+
+    pub fn emoji(self) -> &'static str {
+        match self {
+            Self::Accepted => "✅",
+            Self::Pending => "⚪",
+            Self::Unknown => "❓",
+            Self::Rejected => "❌",
+            Self::Reverted => "↩️",
+            Self::Partial => "🟡",
+            Self::Unreleased => "🔵",
+            Self::EndOfLife => "⚫",
+        }
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl Tree {
-    fn generate(branch: String, found_branches: &mut BTreeSet<OsString>) -> Tree {
-        found_branches.insert((&branch).into());
+    pub fn state(&self) -> State {
+        match self.accepted {
+            Some(true) if self.reverted => State::Reverted,
+            Some(true) if self.unreleased => State::Unreleased,
+            Some(true) => State::Accepted,
+            Some(false) if self.end_of_life => State::EndOfLife,
+            Some(false) if self.partial => State::Partial,
+            Some(false) => State::Pending,
+            None => State::Unknown,
+        }
+    }
+
+    fn generate(graph: &Graph, branch: &str, found_branches: &mut BTreeSet<OsString>) -> Tree {
+        let shared = !found_branches.insert(branch.into());
 
-        let nexts = next_branches(&branch)
-            .into_iter()
-            .map(|b| Self::generate(b.to_string(), found_branches))
-            .collect();
+        let nexts = if shared {
+            Vec::new()
+        } else {
+            graph
+                .nexts(branch)
+                .iter()
+                .map(|next| Self::generate(graph, next, found_branches))
+                .collect()
+        };
 
         Tree {
             accepted: None,
-            branch_name: branch,
+            shared,
+            reverted: false,
+            partial: false,
+            end_of_life: false,
+            unreleased: false,
+            released: None,
+            rewritten: None,
+            backport: None,
+            carried_by: None,
+            branch_url: None,
+            commit_link: None,
+            branch_name: branch.to_string(),
+            packages: Vec::new(),
             children: nexts,
         }
     }
 
+    fn branch_names<'a>(&'a self, out: &mut Vec<&'a str>) {
+        out.push(&self.branch_name);
+
+        for child in self.children.iter() {
+            child.branch_names(out);
+        }
+    }
+
+    /// The node for `branch`, if it's in the tree.
+    pub fn find(&self, branch: &str) -> Option<&Tree> {
+        if self.branch_name == branch {
+            return Some(self);
+        }
+
+        self.children.iter().find_map(|child| child.find(branch))
+    }
+
+    /// Whether the change has reached every branch in the tree.
+    pub fn fully_accepted(&self) -> bool {
+        let accepted = match self.accepted {
+            Some(true) => !self.reverted && !self.unreleased,
+            // It's never going to get there, so it isn't pending.
+            Some(false) => self.end_of_life,
+            None => false,
+        };
+
+        accepted && self.children.iter().all(Tree::fully_accepted)
+    }
+
+    /// Mark the branches of releases that are no longer supported.
+    fn fill_end_of_life(&mut self, releases: &Releases) {
+        self.end_of_life = releases.is_end_of_life(&self.branch_name);
+
+        for child in self.children.iter_mut() {
+            child.fill_end_of_life(releases);
+        }
+    }
+
     fn fill_accepted(&mut self, branches: &BTreeSet<OsString>, missing_means_absent: bool) {
         self.accepted = match branches.contains(OsStr::new(&self.branch_name)) {
             true => Some(true),
@@ -46,13 +329,485 @@ impl Tree {
         }
     }
 
-    pub async fn make(base_branch: String, merge_status: &github::PullRequestStatus, nixpkgs: &Nixpkgs<'_>) -> Tree {
+    fn accepted_branch_names<'a>(&'a self, out: &mut Vec<&'a str>) {
+        if self.accepted == Some(true) {
+            out.push(&self.branch_name);
+        }
+
+        for child in self.children.iter() {
+            child.accepted_branch_names(out);
+        }
+    }
+
+    /// Collect the accepted branches, each with the branch before it
+    /// in the tree, if there is one, and the node itself.
+    fn accepted_nodes<'a>(
+        &'a self,
+        parent: Option<&'a str>,
+        out: &mut Vec<(Option<&'a str>, &'a Tree)>,
+    ) {
+        if self.accepted == Some(true) {
+            out.push((parent, self));
+        }
+
+        for child in self.children.iter() {
+            child.accepted_nodes(Some(&self.branch_name), out);
+        }
+    }
+
+    fn fill_reverted(&mut self, branches: &BTreeSet<OsString>) {
+        self.reverted =
+            self.accepted == Some(true) && branches.contains(OsStr::new(&self.branch_name));
+
+        for child in self.children.iter_mut() {
+            child.fill_reverted(branches);
+        }
+    }
+
+    fn fill_partial(&mut self, all: &BTreeSet<OsString>, some: &BTreeSet<OsString>) {
+        let branch = OsStr::new(&self.branch_name);
+        if self.accepted == Some(true) && !all.contains(branch) {
+            self.accepted = Some(false);
+        }
+        self.partial = self.accepted == Some(false) && some.contains(branch);
+
+        for child in self.children.iter_mut() {
+            child.fill_partial(all, some);
+        }
+    }
+
+    fn fill_released(&mut self, releases: &BTreeMap<String, (bool, Option<String>)>) {
+        if let Some((contains, released)) = releases.get(&self.branch_name) {
+            self.unreleased = !contains;
+            self.released = released.clone();
+        }
+
+        for child in self.children.iter_mut() {
+            child.fill_released(releases);
+        }
+    }
+
+    fn fill_rewritten(&mut self, rewritten: &BTreeMap<String, String>) {
+        self.rewritten = rewritten.get(&self.branch_name).cloned();
+
+        for child in self.children.iter_mut() {
+            child.fill_rewritten(rewritten);
+        }
+    }
+
+    fn fill_packages(&mut self, versions: &BTreeMap<String, Vec<PackageVersion>>) {
+        if let Some(packages) = versions.get(&self.branch_name) {
+            self.packages = packages.clone();
+        }
+
+        for child in self.children.iter_mut() {
+            child.fill_packages(versions);
+        }
+    }
+
+    /// Fill in the versions of the packages touched by `merge_commit`
+    /// on each branch.
+    pub async fn find_packages(
+        &mut self,
+        merge_commit: &str,
+        nixpkgs: &Nixpkgs<'_>,
+    ) -> Result<(), nixpkgs::Error> {
+        let changed = packages::changed_packages(nixpkgs, merge_commit).await?;
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let mut branches = Vec::new();
+        self.branch_names(&mut branches);
+
+        let mut versions = BTreeMap::new();
+        for branch in branches {
+            let branch_ref = nixpkgs.branch_ref(branch);
+
+            let mut branch_versions = Vec::with_capacity(changed.len());
+            for (package, before) in changed.iter() {
+                branch_versions.push(PackageVersion {
+                    attr: package.attr.clone(),
+                    before: before.clone(),
+                    version: packages::version_at(nixpkgs, &branch_ref, package).await?,
+                });
+            }
+
+            versions.insert(branch.to_string(), branch_versions);
+        }
+
+        self.fill_packages(&versions);
+        Ok(())
+    }
+
+    /// Check that branches contain every one of `landed_commits`,
+    /// rather than just the merge commit, and mark the branches that
+    /// only contain some of them.  Each landed commit is the parent
+    /// of the next, so a branch has some of them if it has the first,
+    /// and all of them if it has the last.
+    pub async fn find_partial(
+        &mut self,
+        landed_commits: &[String],
+        nixpkgs: &Nixpkgs<'_>,
+    ) -> Result<(), nixpkgs::Error> {
+        let (first, last) = match landed_commits {
+            [first, .., last] => (first, last),
+            _ => return Ok(()),
+        };
+
+        let mut some = BTreeSet::new();
+        nixpkgs.branches_containing_commit(first, &mut some).await?;
+
+        let mut all = BTreeSet::new();
+        nixpkgs.branches_containing_commit(last, &mut all).await?;
+
+        self.fill_partial(&all, &some);
+        Ok(())
+    }
+
+    fn fill_links(
+        &mut self,
+        links: &WebLinks,
+        merge_commit: Option<&str>,
+        first_commits: &BTreeMap<String, String>,
+    ) {
+        self.branch_url = Some(links.branch(&self.branch_name));
+        self.commit_link = match (self.accepted, merge_commit) {
+            (Some(true), _) => first_commits
+                .get(&self.branch_name)
+                .map(|commit| CommitLink {
+                    url: links.commit(commit),
+                    text: commit.get(..12).unwrap_or(commit).to_string(),
+                }),
+            (Some(false), Some(merge_commit)) => Some(CommitLink {
+                url: links.compare(&self.branch_name, merge_commit),
+                text: "compare".to_string(),
+            }),
+            _ => None,
+        };
+
+        for child in self.children.iter_mut() {
+            child.fill_links(links, merge_commit, first_commits);
+        }
+    }
+
+    /// Check whether the latest release of each channel whose branch
+    /// has `merge_commit` has it too.
+    pub async fn find_releases(
+        &mut self,
+        merge_commit: &str,
+        channels: &Channels<'_>,
+        nixpkgs: &Nixpkgs<'_>,
+    ) {
+        let mut branches = Vec::new();
+        self.accepted_branch_names(&mut branches);
+
+        let mut releases = BTreeMap::new();
+        for branch in branches.into_iter().filter(|branch| is_channel(branch)) {
+            let release = match channels.release(branch).await {
+                Ok(release) => release,
+                Err(forge::Error::NotFound) => continue,
+                Err(e) => {
+                    eprintln!("pr-tracker: release: {}", e);
+                    continue;
+                }
+            };
+
+            match nixpkgs.is_ancestor(merge_commit, &release.commit).await {
+                Ok(contains) => {
+                    releases.insert(branch.to_string(), (contains, release.released));
+                }
+                Err(e) => eprintln!("pr-tracker: is_ancestor: {}", e),
+            }
+        }
+
+        self.fill_released(&releases);
+    }
+
+    /// Link each branch to its page on the forge, and to the first
+    /// commit on it to contain the change, or a comparison with the
+    /// change if it hasn't reached the branch yet.
+    pub async fn find_links(
+        &mut self,
+        links: &WebLinks,
+        merge_status: &forge::PullRequestStatus,
+        nixpkgs: &Nixpkgs<'_>,
+    ) {
+        let merge_commit = match merge_status {
+            forge::PullRequestStatus::Merged {
+                merge_commit_oid: Some(merge_commit),
+            } => Some(merge_commit.as_str()),
+            _ => None,
+        };
+
+        let mut first_commits = BTreeMap::new();
+        if let Some(merge_commit) = merge_commit {
+            let mut nodes = Vec::new();
+            self.accepted_nodes(None, &mut nodes);
+
+            // Each git lookup walks the branch's whole history since
+            // the change, so only look up the first branch on each
+            // first-parent line.  Channel branches are fast-forwarded
+            // to commits on the branch before them, so they share its
+            // line, and the carrier is where a staging-next cycle
+            // merged the change into the next line.
+            for (parent, node) in nodes {
+                let branch = node.branch_name.as_str();
+                let cycle = parent.map_or(false, is_staging_next) || is_staging_next(branch);
+                let known = match (&node.carried_by, parent) {
+                    (Some(carrier), _) => Some(carrier.commit.clone()),
+                    (None, Some(parent)) if !cycle => first_commits.get(parent).cloned(),
+                    _ => None,
+                };
+
+                let first = match known {
+                    Some(commit) => Ok(commit),
+                    None => nixpkgs.first_containing(branch, merge_commit).await,
+                };
+                match first {
+                    Ok(commit) => {
+                        first_commits.insert(branch.to_string(), commit);
+                    }
+                    Err(e) => eprintln!("pr-tracker: first_containing: {}", e),
+                }
+            }
+        }
+
+        self.fill_links(links, merge_commit, &first_commits);
+    }
+
+    /// Collect the accepted branches on either side of a staging-next
+    /// cycle.
+    fn staging_next_branches(&self, branches: &mut Vec<String>) {
+        for child in &self.children {
+            let cycle = is_staging_next(&child.branch_name) || is_staging_next(&self.branch_name);
+            if cycle && child.accepted == Some(true) {
+                branches.push(child.branch_name.clone());
+            }
+
+            child.staging_next_branches(branches);
+        }
+    }
+
+    fn fill_carriers(&mut self, carriers: &mut BTreeMap<String, Carrier>) {
+        self.carried_by = carriers.remove(&self.branch_name);
+
+        for child in self.children.iter_mut() {
+            child.fill_carriers(carriers);
+        }
+
+        // staging is merged into staging-next directly, so the PR for
+        // the cycle is the one that merges staging-next into the next
+        // branch.
+        if !is_staging_next(&self.branch_name) {
+            return;
+        }
+        let next = self
+            .children
+            .iter()
+            .filter_map(|child| child.carried_by.as_ref())
+            .find(|next| next.pr_number.is_some());
+        if let (Some(carrier), Some(next)) = (self.carried_by.as_mut(), next) {
+            if carrier.pr_number.is_none() {
+                carrier.pr_number = next.pr_number;
+                carrier.title = next.title.clone();
+            }
+        }
+    }
+
+    /// Find the merges that brought `merge_commit` into staging-next,
+    /// and from staging-next into the next branch, and link to them
+    /// on the Hydra instance at `hydra_url`, if there is one.
+    pub async fn find_carriers(
+        &mut self,
+        merge_commit: &str,
+        hydra_url: Option<&str>,
+        nixpkgs: &Nixpkgs<'_>,
+    ) -> Result<(), nixpkgs::Error> {
+        let mut branches = Vec::new();
+        self.staging_next_branches(&mut branches);
+
+        let mut carriers = BTreeMap::new();
+        for branch in branches {
+            let entry = match nixpkgs.carrying_merge(&branch, merge_commit).await? {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            let change = Change::from_log_entry(entry);
+            // Hydra finds evaluations by the commits they were of.
+            let hydra_url = hydra_url.map(|url| format!("{}/search?query={}", url, change.commit));
+            let carrier = Carrier {
+                commit: change.commit,
+                pr_number: change.pr_number,
+                title: change.pr_number.map(|_| change.title),
+                hydra_url,
+            };
+            carriers.insert(branch, carrier);
+        }
+
+        self.fill_carriers(&mut carriers);
+        Ok(())
+    }
+
+    /// Mark the branches where `merge_commit` has since been
+    /// reverted, either by a commit that says so in its message, or
+    /// by one of `forge_reverts`, which the forge told us about, and
+    /// hasn't been put back since by reverting the revert.
+    pub async fn find_reverts(
+        &mut self,
+        merge_commit: &str,
+        forge_reverts: Vec<forge::Revert>,
+        nixpkgs: &Nixpkgs<'_>,
+    ) -> Result<(), nixpkgs::Error> {
+        let mut branches = Vec::new();
+        self.accepted_branch_names(&mut branches);
+        if branches.is_empty() {
+            return Ok(());
+        }
+
+        // Reverting a merge commit says which commit was reverted,
+        // but so does reverting the PR's last commit, which is the
+        // merge commit's second parent.
+        let mut reverted = nixpkgs.parents(merge_commit).await?;
+        if !reverted.is_empty() {
+            reverted.remove(0);
+        }
+        reverted.push(merge_commit.to_string());
+
+        let mut reverts = nixpkgs.reverts(&branches, merge_commit, &reverted).await?;
+        let mut relands = Vec::new();
+        for revert in forge_reverts {
+            reverts.push(revert.merge_commit);
+            relands.extend(revert.relands);
+        }
+
+        if reverts.is_empty() {
+            return Ok(());
+        }
+        relands.extend(nixpkgs.reverts(&branches, merge_commit, &reverts).await?);
+
+        let reverted_branches = branches_containing(&reverts, nixpkgs).await;
+        let relanded_branches = branches_containing(&relands, nixpkgs).await;
+        self.fill_reverted(&(&reverted_branches - &relanded_branches));
+        Ok(())
+    }
+
+    /// Note the branches that don't contain `merge_commit`, but were
+    /// `seen` to before they were rewritten, so the change doesn't
+    /// just look like it's gone back to pending for no reason.
+    pub async fn find_rewrites(&mut self, merge_commit: &str, seen: &Heads, nixpkgs: &Nixpkgs<'_>) {
+        let mut branches = Vec::new();
+        self.branch_names(&mut branches);
+
+        let mut rewritten = BTreeMap::new();
+        for branch in branches {
+            if self.find(branch).and_then(|node| node.accepted) != Some(false) {
+                continue;
+            }
+
+            let time = match seen.last_rewritten(branch) {
+                Some(time) => time,
+                None => continue,
+            };
+
+            for commit in seen.previous(branch) {
+                // Old heads might have been garbage collected since.
+                match nixpkgs.is_ancestor(merge_commit, &commit).await {
+                    Ok(false) => continue,
+                    Ok(true) => {}
+                    Err(e) => {
+                        eprintln!("pr-tracker: is_ancestor: {}", e);
+                        continue;
+                    }
+                }
+
+                let time = humantime::format_rfc3339_seconds(time).to_string();
+                rewritten.insert(branch.to_string(), time);
+                break;
+            }
+        }
+
+        self.fill_rewritten(&rewritten);
+    }
+
+    /// Add a subtree for each of `branches` that the change is to be
+    /// backported to, in the state of the backport PR for that
+    /// branch, if there is one among `prs`.
+    pub async fn add_backports(
+        &mut self,
+        branches: &[&str],
+        prs: &[forge::LinkedPr],
+        links: &WebLinks,
+        releases: &Releases,
+        nixpkgs: &Nixpkgs<'_>,
+    ) {
+        for branch in branches {
+            if self.find(branch).is_some() || releases.is_long_gone(branch) {
+                continue;
+            }
+
+            // There might have been more than one attempt at the
+            // backport, so prefer one that's got further.
+            let pr = prs
+                .iter()
+                .filter(|pr| pr.branch == *branch)
+                .filter(|pr| !matches!(pr.status, forge::PullRequestStatus::Closed))
+                .max_by_key(|pr| matches!(pr.status, forge::PullRequestStatus::Merged { .. }));
+
+            let open = forge::PullRequestStatus::Open;
+            let (status, backport) = match pr {
+                Some(pr) => {
+                    let state = match pr.status {
+                        forge::PullRequestStatus::Merged { .. } => BackportStatus::Merged,
+                        _ => BackportStatus::Open,
+                    };
+                    let backport = Backport {
+                        number: Some(pr.number),
+                        url: Some(pr.url.clone()),
+                        status: state,
+                    };
+                    (&pr.status, backport)
+                }
+                None => {
+                    let backport = Backport {
+                        number: None,
+                        url: None,
+                        status: BackportStatus::Pending,
+                    };
+                    (&open, backport)
+                }
+            };
+
+            let subtree = Self::make(branch.to_string(), status, releases, nixpkgs).await;
+            let mut subtree = match subtree {
+                Ok(subtree) => subtree,
+                Err(e) => {
+                    eprintln!("pr-tracker: {}", e);
+                    continue;
+                }
+            };
+            subtree.find_links(links, status, nixpkgs).await;
+            subtree.backport = Some(backport);
+            self.children.push(subtree);
+        }
+    }
+
+    /// The tree of branches after `base_branch`, besides those of
+    /// releases that are long gone, and whether each has the change.
+    pub async fn make(
+        base_branch: String,
+        merge_status: &forge::PullRequestStatus,
+        releases: &Releases,
+        nixpkgs: &Nixpkgs<'_>,
+    ) -> Result<Tree, branches::Error> {
         let mut missing_means_absent = true;
         let mut branches = BTreeSet::new();
 
-        let mut tree = Self::generate(base_branch.clone(), &mut branches);
+        let graph = Graph::supported(&base_branch, releases)?;
+        let mut tree = Self::generate(&graph, graph.root(), &mut branches);
 
-        if let github::PullRequestStatus::Merged {
+        if let forge::PullRequestStatus::Merged {
             merge_commit_oid, ..
         } = merge_status
         {
@@ -86,6 +841,34 @@ impl Tree {
         }
 
         tree.fill_accepted(&branches, missing_means_absent);
-        tree
+        tree.fill_end_of_life(releases);
+
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared() {
+        let graph = Graph::with_nexts("a", &|branch| match branch {
+            "a" => vec!["b".to_string(), "c".to_string()],
+            "b" | "c" => vec!["d".to_string()],
+            _ => vec![],
+        })
+        .unwrap();
+
+        let tree = Tree::generate(&graph, graph.root(), &mut BTreeSet::new());
+        let first = &tree.children[0].children[0];
+        let second = &tree.children[1].children[0];
+        assert_eq!((first.branch_name.as_str(), first.shared), ("d", false));
+        assert_eq!((second.branch_name.as_str(), second.shared), ("d", true));
+
+        let mermaid = crate::export::mermaid(None, Some(&tree));
+        assert_eq!(mermaid.matches("[\"d\"]").count(), 1);
+        assert!(mermaid.contains("n1 --> n2"));
+        assert!(mermaid.contains("n3 --> n2"));
     }
 }
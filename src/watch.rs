@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later WITH GPL-3.0-linking-exception
+// SPDX-FileCopyrightText: 2021 Alyssa Ross <hi@alyssa.is>
+
+//! A dashboard of the PRs a user is keeping an eye on.
+//!
+//! The list of PRs is kept in a cookie, so there's no need for
+//! accounts, or for anything to be stored on the server.  The cookie
+//! is signed, and only updated by same-origin requests, so other
+//! sites can't change what a user is watching.
+
+use askama::Template;
+use futures_util::future::join_all;
+use http_types::{mime, StatusCode};
+use serde::Deserialize;
+use tide::http::cookies::{CookieJar, SameSite};
+use tide::http::Cookie;
+use tide::{Redirect, Request, Response};
+
+use crate::forge::{self, Forge, PullRequestStatus};
+use crate::history;
+use crate::nixpkgs::Nixpkgs;
+use crate::ogmeta::Ogmeta;
+use crate::releases::Releases;
+use crate::tree::Tree;
+use crate::{CONFIG, COOKIE_KEY, RELEASES};
+
+const COOKIE: &str = "watched";
+
+/// Every watched PR is looked up each time the dashboard is loaded,
+/// so there has to be a limit.
+const MAX_WATCHED: usize = 50;
+
+/// The branch most users are waiting for a change to reach.
+const UNSTABLE: &str = "nixos-unstable";
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Filter {
+    NotInUnstable,
+    Done,
+}
+
+impl Filter {
+    fn matches(self, pr: &WatchedPr) -> bool {
+        match self {
+            Self::NotInUnstable => pr.in_unstable == Some(false),
+            Self::Done => pr.done,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WatchedPr {
+    pub number: i64,
+    pub title: Option<String>,
+    pub closed: bool,
+
+    /// Whether the PR has reached nixos-unstable, or `None` if it
+    /// never will, because nixos-unstable isn't in its tree.
+    pub in_unstable: Option<bool>,
+
+    /// Whether the PR has reached every branch it's going to.
+    pub done: bool,
+
+    pub summary: Option<Ogmeta>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "watch.html")]
+struct WatchTemplate {
+    mount: String,
+    prs: Vec<WatchedPr>,
+
+    /// The number of watched PRs, before filtering.
+    watching: usize,
+
+    full: bool,
+    source_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    filter: Option<Filter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Deserialize)]
+struct Form {
+    pr: i64,
+    action: Action,
+}
+
+fn watched<S>(request: &Request<S>) -> Vec<i64> {
+    let mut jar = CookieJar::new();
+    if let Some(cookie) = request.cookie(COOKIE) {
+        jar.add_original(cookie);
+    }
+
+    // A cookie with a bad signature is ignored, as if it wasn't there.
+    let cookie = match jar.signed(&COOKIE_KEY).get(COOKIE) {
+        Some(cookie) => cookie,
+        None => return Vec::new(),
+    };
+
+    cookie
+        .value()
+        .split('.')
+        .filter_map(|number| number.parse().ok())
+        .take(MAX_WATCHED)
+        .collect()
+}
+
+fn cookie(path: String, prs: &[i64]) -> Cookie<'static> {
+    let value: Vec<_> = prs.iter().map(i64::to_string).collect();
+
+    let cookie = Cookie::build(COOKIE, value.join("."))
+        .path(path)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .permanent()
+        .finish();
+
+    let mut jar = CookieJar::new();
+    jar.signed(&COOKIE_KEY).add(cookie);
+    jar.get(COOKIE).unwrap().clone()
+}
+
+/// Whether `request` came from one of our own pages, going by the
+/// Origin header browsers send with POST requests, or, failing that,
+/// the Sec-Fetch-Site header.
+fn same_origin<S>(request: &Request<S>) -> bool {
+    if let Some(origin) = request.header("Origin") {
+        let authority = origin.last().as_str().split_once("://");
+        return authority.map(|(_, authority)| authority) == request.host();
+    }
+
+    match request.header("Sec-Fetch-Site") {
+        Some(site) => site.last().as_str() != "cross-site",
+        None => true,
+    }
+}
+
+pub async fn watched_pr(
+    forge: &dyn Forge,
+    nixpkgs: &Nixpkgs<'_>,
+    releases: &Releases,
+    number: i64,
+) -> WatchedPr {
+    let mut pr = WatchedPr {
+        number,
+        title: None,
+        closed: false,
+        in_unstable: None,
+        done: false,
+        summary: None,
+        error: None,
+    };
+
+    let mut info = match forge.pr_info(number).await {
+        Ok(info) => info,
+        Err(forge::Error::NotFound) => {
+            pr.error = Some("No such PR.".to_string());
+            return pr;
+        }
+        Err(e) => {
+            pr.error = Some(e.to_string());
+            return pr;
+        }
+    };
+
+    if let Err(e) = history::resolve_merge_commit(nixpkgs, &mut info).await {
+        eprintln!("pr-tracker: resolve_merge_commit: {}", e);
+    }
+
+    pr.title = Some(info.title);
+
+    if matches!(info.status, PullRequestStatus::Closed) {
+        pr.closed = true;
+        return pr;
+    }
+
+    let tree = match Tree::make(info.branch, &info.status, releases, nixpkgs).await {
+        Ok(tree) => tree,
+        Err(e) => {
+            pr.error = Some(e.to_string());
+            return pr;
+        }
+    };
+    pr.in_unstable = tree.find(UNSTABLE).map(|node| node.accepted == Some(true));
+    pr.done = tree.fully_accepted();
+    pr.summary = Some(Ogmeta::from_tree(&tree));
+    pr
+}
+
+pub async fn handle_watch<S>(request: Request<S>) -> http_types::Result<Response> {
+    let query = request.query::<Query>()?;
+    let watched = watched(&request);
+
+    let forge = crate::forge();
+    let nixpkgs = Nixpkgs::new(&CONFIG.path, &CONFIG.remote);
+
+    let prs = join_all(
+        watched
+            .iter()
+            .map(|number| watched_pr(&*forge, &nixpkgs, &RELEASES, *number)),
+    )
+    .await;
+
+    let page = WatchTemplate {
+        mount: crate::route(""),
+        prs: prs
+            .into_iter()
+            .filter(|pr| query.filter.map_or(true, |filter| filter.matches(pr)))
+            .collect(),
+        watching: watched.len(),
+        full: watched.len() >= MAX_WATCHED,
+        source_url: CONFIG.source_url.clone(),
+    };
+
+    Ok(Response::builder(200)
+        .content_type(mime::HTML)
+        .body(page.render()?)
+        .build())
+}
+
+pub async fn handle_watch_update<S>(mut request: Request<S>) -> http_types::Result<Response> {
+    if !same_origin(&request) {
+        return Err(http_types::Error::from_str(
+            StatusCode::Forbidden,
+            "Watched PRs can only be changed from this site.",
+        ));
+    }
+
+    let form = request.body_form::<Form>().await?;
+    if form.pr <= 0 {
+        return Err(http_types::Error::from_str(
+            StatusCode::BadRequest,
+            format!("Invalid PR number: {}", form.pr),
+        ));
+    }
+
+    let mut watched = watched(&request);
+
+    match form.action {
+        Action::Add if watched.contains(&form.pr) => {}
+        Action::Add if watched.len() >= MAX_WATCHED => {
+            return Err(http_types::Error::from_str(
+                StatusCode::BadRequest,
+                format!("Can't watch more than {} PRs.", MAX_WATCHED),
+            ));
+        }
+        Action::Add => watched.push(form.pr),
+        Action::Remove => watched.retain(|number| *number != form.pr),
+    }
+
+    let path = request.url().path().to_string();
+    let mut response: Response = Redirect::see_other(&path).into();
+    response.insert_cookie(cookie(path, &watched));
+    Ok(response)
+}